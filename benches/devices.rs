@@ -0,0 +1,70 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use uxn_rs::devices::{ConsoleDevice, CONSOLE};
+use uxn_rs::uxn::{Device, ExecutionResult, PortAddress, Uxn};
+
+// writes 0x1000 characters to the console, each followed by a write to a port the console
+// doesn't handle
+const CONSOLE_SPAM: &str = "
+    #1000
+    &loop
+        #41 #18 DEO
+        #00 #1a DEO
+        #0001 SUB2 DUP2 #0000 NEQ2 ,&loop JCN
+    POP2
+    BRK";
+
+/// A console the VM doesn't recognize, so that it is called through the box like any other
+/// device instead of directly.
+struct BoxedConsole(ConsoleDevice);
+
+impl Device for BoxedConsole {
+    fn dei(&self, port: PortAddress) -> ExecutionResult<u8> {
+        self.0.dei(port)
+    }
+
+    fn deo(&mut self, port: PortAddress, value: u8) -> ExecutionResult<()> {
+        self.0.deo(port, value)
+    }
+
+    fn dei_mask(&self) -> u16 {
+        self.0.dei_mask()
+    }
+
+    fn deo_mask(&self) -> u16 {
+        self.0.deo_mask()
+    }
+
+    fn name(&self) -> &'static str {
+        self.0.name()
+    }
+
+    fn on_halt(&mut self) -> ExecutionResult<()> {
+        self.0.on_halt()
+    }
+}
+
+fn console_spam(c: &mut Criterion) {
+    let rom = uxn_rs::assembler::assemble_program(&format!("@main {}", CONSOLE_SPAM))
+        .unwrap()
+        .rom;
+    let mut group = c.benchmark_group("console spam");
+    group.bench_function("direct", |b| {
+        b.iter(|| {
+            let mut uxn = Uxn::try_from(rom.as_slice()).unwrap();
+            uxn.set_device(CONSOLE, Box::new(ConsoleDevice::with_capture()));
+            uxn.eval(0x100).unwrap();
+        })
+    });
+    group.bench_function("boxed", |b| {
+        b.iter(|| {
+            let mut uxn = Uxn::try_from(rom.as_slice()).unwrap();
+            let console = BoxedConsole(ConsoleDevice::with_capture());
+            uxn.set_device(CONSOLE, Box::new(console));
+            uxn.eval(0x100).unwrap();
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, console_spam);
+criterion_main!(benches);
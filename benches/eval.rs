@@ -0,0 +1,59 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use uxn_rs::devices::{ConsoleDevice, CONSOLE};
+use uxn_rs::uxn::{EvalOutcome, Uxn};
+
+// instructions run per iteration, the ROMs below loop forever
+const BUDGET: u64 = 100_000;
+
+// arithmetic on a short counter
+const ARITHMETIC: &str = "
+    #0000
+    &loop
+        INC2 DUP2 #0003 MUL2 #0007 EOR2 #0002 DIV2 POP2
+        ,&loop JMP";
+
+// copies the page at 0x2000 to 0x3000, over and over
+const MEMORY_COPY: &str = "
+    #0000
+    &loop
+        DUP2 #3000 ADD2 OVR2 #2000 ADD2 LDA ROT ROT STA
+        INC2 #00ff AND2
+        ,&loop JMP";
+
+// writes to the console and reads the stack pointer through the system device
+const DEVICE_IO: &str = "
+    &loop
+        #41 #18 DEO
+        #02 DEI POP
+        ,&loop JMP";
+
+/// Boots a VM with `rom` and a console and runs it for the instruction budget, returning the
+/// number of instructions that ran.
+fn run_budget(rom: &[u8], budget: u64) -> u64 {
+    let mut uxn = Uxn::new();
+    let entry = uxn.boot_rom(rom).unwrap();
+    uxn.set_device(CONSOLE, Box::new(ConsoleDevice::with_capture()));
+    uxn.set_pc(entry);
+    let (outcome, used) = uxn.eval_budget(budget);
+    assert_eq!(outcome, EvalOutcome::Stepped);
+    used
+}
+
+fn eval_loop(c: &mut Criterion) {
+    let mut group = c.benchmark_group("eval");
+    group.throughput(Throughput::Elements(BUDGET));
+    for (name, src) in [
+        ("arithmetic", ARITHMETIC),
+        ("memory copy", MEMORY_COPY),
+        ("device io", DEVICE_IO),
+    ] {
+        let rom = uxn_rs::assembler::assemble_program(&format!("@main {}", src))
+            .unwrap()
+            .rom;
+        group.bench_function(name, |b| b.iter(|| run_budget(&rom, BUDGET)));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, eval_loop);
+criterion_main!(benches);
@@ -0,0 +1,39 @@
+//! Assembles a small demo, runs it, and writes the screen to `demo.ppm`.
+//!
+//! `cargo run --example demo`
+
+use std::fs;
+use uxn_rs::assembler::assemble;
+use uxn_rs::devices::{ScreenDevice, SCREEN};
+use uxn_rs::uxn::Uxn;
+
+const SOURCE: &str = include_str!("demo.tal");
+
+/// Assembles and runs the demo, returning the VM with its screen drawn.
+pub fn run() -> Uxn {
+    let assembled = assemble(SOURCE).unwrap();
+    let mut uxn = Uxn::try_from(assembled.rom.as_slice()).unwrap();
+    uxn.set_device(SCREEN, Box::new(ScreenDevice::new(64, 64)));
+    uxn.eval(0x100).unwrap();
+    uxn
+}
+
+/// The screen as a binary PPM, colored with the system device's palette.
+pub fn ppm(uxn: &Uxn) -> Vec<u8> {
+    let screen = uxn.device(SCREEN).downcast_ref::<ScreenDevice>().unwrap();
+    let palette = uxn.palette();
+    let mut out = format!("P6\n{} {}\n255\n", screen.width(), screen.height()).into_bytes();
+    for y in 0..screen.height() {
+        for x in 0..screen.width() {
+            out.extend(palette[screen.pixel(x, y) as usize]);
+        }
+    }
+    out
+}
+
+#[allow(dead_code)]
+fn main() {
+    let uxn = run();
+    fs::write("demo.ppm", ppm(&uxn)).unwrap();
+    println!("wrote demo.ppm");
+}
@@ -1,13 +1,20 @@
-use enum_derive::ParseEnumError;
-use crate::uxn::{InstructionMode, Opcode};
+use crate::disassembler::mode_suffix;
+use crate::uxn::{encode_byte, InstructionMode, Opcode, JCI, JMI};
 use nom::branch::{alt, permutation};
-use nom::bytes::complete::{tag, take_until};
-use nom::character::complete::{alpha1, alphanumeric1, char, multispace1, none_of, one_of};
-use nom::combinator::{map, map_res, not, opt, recognize, value};
-use nom::error::{ErrorKind, ParseError};
-use nom::multi::{count, many0_count, many1, many_till};
-use nom::sequence::{pair, preceded, tuple};
-use nom::{error, IResult, Parser};
+use nom::bytes::complete::{is_not, tag};
+use nom::character::complete::{alpha1, alphanumeric1, char, multispace1, one_of};
+use nom::combinator::{eof, map, map_res, opt, peek, recognize, value};
+use nom::error::ParseError;
+use nom::multi::{count, many0_count, many1};
+use nom::sequence::{pair, preceded, terminated, tuple};
+use nom::{IResult, Parser};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+
+/// Address at which ROMs are loaded, and where the emitted ROM image starts.
+pub const PAGE_PROGRAM: u16 = 0x0100;
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Token {
@@ -24,24 +31,154 @@ pub enum Token {
         mode: AddressingMode,
         address: u16,
     },
+    Reference {
+        mode: AddressingMode,
+        label: String,
+    },
+    Padding {
+        type_: PaddingType,
+        size: u16,
+    },
+    RawData(Vec<u8>),
+}
+
+/// Writes the token back as uxntal, e.g. `#12`, `;label` or `ADD2k`. Raw data is written as
+/// hex bytes.
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Instruction {
+                opcode: Opcode::LIT,
+                mode,
+                immediate,
+            } if mode.is_keep() => {
+                let digits = if mode.is_short() { 4 } else { 2 };
+                if mode.is_return() {
+                    let suffix = mode_suffix(*mode & !InstructionMode::Keep);
+                    write!(f, "LIT{} {:02$x}", suffix, immediate, digits)
+                } else {
+                    write!(f, "#{:01$x}", immediate, digits)
+                }
+            }
+            Token::Instruction {
+                opcode: Opcode::LIT,
+                mode,
+                ..
+            } => write!(f, "BRK{}", mode_suffix(*mode)),
+            Token::Instruction { opcode, mode, .. } => write!(f, "{:?}{}", opcode, mode_suffix(*mode)),
+            Token::Label {
+                name,
+                type_: LabelType::Parent,
+            } => write!(f, "@{}", name),
+            Token::Label {
+                name,
+                type_: LabelType::Child,
+            } => write!(f, "&{}", name),
+            Token::Label {
+                type_: LabelType::Anonymous,
+                ..
+            } => write!(f, "}}"),
+            Token::Address { mode, address } => match mode {
+                AddressingMode::RawAbsolute | AddressingMode::LiteralAbsolute => {
+                    write!(f, "{}{:04x}", mode.rune(), address)
+                }
+                _ => write!(f, "{}{:02x}", mode.rune(), address),
+            },
+            Token::Reference { mode, label } if is_anonymous(label) => write!(f, "{}{{", mode.rune()),
+            Token::Reference { mode, label } => write!(f, "{}{}", mode.rune(), label),
+            Token::Padding {
+                type_: PaddingType::Absolute,
+                size,
+            } => write!(f, "|{:04x}", size),
+            Token::Padding {
+                type_: PaddingType::Relative,
+                size,
+            } => write!(f, "${:x}", size),
+            Token::RawData(bytes) => {
+                let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+                write!(f, "{}", hex.join(" "))
+            }
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
-enum LabelType {
+pub enum LabelType {
     Parent,
     Child,
+    /// The `}` closing a `?{ }` or `!{ }` block, which doesn't change the parent label.
+    Anonymous,
+}
+
+/// Whether `label` names the end of a `?{ }` or `!{ }` block.
+fn is_anonymous(label: &str) -> bool {
+    label.starts_with('{')
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
-enum AddressingMode {
+pub enum AddressingMode {
     LiteralRelative,
     LiteralZeroPage,
     RawAbsolute,
     LiteralAbsolute,
+    /// A `JCI` to the end of a `?{ }` block.
+    ImmediateConditional,
+    /// A `JMI` to the end of a `!{ }` block.
+    ImmediateJump,
+}
+
+impl AddressingMode {
+    fn rune(&self) -> char {
+        match self {
+            AddressingMode::LiteralRelative => ',',
+            AddressingMode::LiteralZeroPage => '.',
+            AddressingMode::RawAbsolute => ':',
+            AddressingMode::LiteralAbsolute => ';',
+            AddressingMode::ImmediateConditional => '?',
+            AddressingMode::ImmediateJump => '!',
+        }
+    }
 }
 
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum PaddingType {
+    Absolute,
+    Relative,
+}
+
+/// Parses a `( comment )`, which may contain nested parentheses.
 pub fn inline_comment<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, (), E> {
-    value((), tuple((tag("("), take_until(")"), tag(")"))))(i)
+    value(
+        (),
+        tuple((
+            tag("("),
+            many0_count(alt((value((), is_not("()")), inline_comment))),
+            tag(")"),
+        )),
+    )(i)
+}
+
+/// Replaces every comment in `src` with spaces, keeping line breaks, so that the remaining
+/// tokens stay at their original line and column.
+pub fn strip_comments(src: &str) -> String {
+    let mut out = String::with_capacity(src.len());
+    let mut input = src;
+    while let Some(c) = input.chars().next() {
+        let len = if let Ok((rest, _)) = inline_comment::<nom::error::Error<&str>>(input) {
+            let comment = &input[..input.len() - rest.len()];
+            out.extend(comment.chars().map(|c| if c.is_whitespace() { c } else { ' ' }));
+            comment.len()
+        } else if c.is_whitespace() {
+            out.push(c);
+            c.len_utf8()
+        } else {
+            let word = input.find(char::is_whitespace).unwrap_or(input.len());
+            out.push_str(&input[..word]);
+            word
+        };
+        input = &input[len..];
+    }
+    out
 }
 
 pub fn hexadecimal(input: &str) -> IResult<&str, u16> {
@@ -55,7 +192,7 @@ pub fn hexadecimal(input: &str) -> IResult<&str, u16> {
 pub fn identifier(input: &str) -> IResult<&str, &str> {
     recognize(pair(
         alt((alpha1, tag("_"))),
-        many0_count(alt((alphanumeric1, tag("_")))),
+        many0_count(alt((alphanumeric1, tag("_"), tag("-"), tag("/")))),
     ))(input)
 }
 
@@ -74,29 +211,57 @@ pub fn either_or<I: Clone, O: Clone, O2, E: ParseError<I>, F>(success_value: O,
 
 // actual uxntal elements
 
+/// Parses a `"word` literal, returning the characters after the quote.
 pub fn ascii_literal(input: &str) -> IResult<&str, &str> {
-    recognize(pair(tag("\""), many1(not(multispace1))))(input)
+    preceded(tag("\""), is_not(" \t\r\n"))(input)
 }
 
+/// Parses a bare lowercase hex byte (`ff`) or short (`abcd`) into raw data.
+pub fn raw_hex(input: &str) -> IResult<&str, Token> {
+    map_res(
+        recognize(many1(one_of("0123456789abcdef"))),
+        |out: &str| match out.len() {
+            2 | 4 => u16::from_str_radix(out, 16)
+                .map(|v| Token::RawData(v.to_be_bytes()[(4 - out.len()) / 2..].to_vec()))
+                .or(Err("Could not parse raw hex")),
+            _ => Err("Raw hex needs to be a byte or a short"),
+        },
+    )(input)
+}
+
+fn addressing_mode(input: &str) -> IResult<&str, AddressingMode> {
+    alt((
+        value(AddressingMode::LiteralRelative, tag(",")),
+        value(AddressingMode::LiteralZeroPage, tag(".")),
+        // `=` is the newer spelling of `:`, as used for jump tables
+        value(AddressingMode::RawAbsolute, alt((tag(":"), tag("=")))),
+        value(AddressingMode::LiteralAbsolute, tag(";")),
+    ))(input)
+}
 
 pub fn address(input: &str) -> IResult<&str, Token> {
-    let (input, (mode, address)) = tuple((
-        alt((
-            value(AddressingMode::LiteralRelative, tag(",")),
-            value(AddressingMode::LiteralZeroPage, tag(".")),
-            value(AddressingMode::RawAbsolute, tag(":")),
-            value(AddressingMode::LiteralAbsolute, tag(";")),
-        )),
-        hexadecimal,
-    ))(input)?;
+    let (input, (mode, address)) = tuple((addressing_mode, hexadecimal))(input)?;
     Ok((input, Token::Address { mode, address }))
 }
 
+/// Parses a reference to a label through one of the addressing runes, e.g. `;main` or `,&loop`.
+pub fn reference(input: &str) -> IResult<&str, Token> {
+    let (input, (mode, label)) =
+        tuple((addressing_mode, recognize(pair(opt(tag("&")), identifier))))(input)?;
+    Ok((
+        input,
+        Token::Reference {
+            mode,
+            label: label.to_string(),
+        },
+    ))
+}
+
 pub fn label(input: &str) -> IResult<&str, Token> {
     let (input, (type_, name)) = tuple((
         alt((
             value(LabelType::Parent, tag("@")),
-            value(LabelType::Child, tag(":")),
+            value(LabelType::Child, tag("&")),
         )),
         identifier,
     ))(input)?;
@@ -109,19 +274,35 @@ pub fn label(input: &str) -> IResult<&str, Token> {
     ))
 }
 
+pub fn padding(input: &str) -> IResult<&str, Token> {
+    let (input, (type_, size)) = tuple((
+        alt((
+            value(PaddingType::Absolute, tag("|")),
+            value(PaddingType::Relative, tag("$")),
+        )),
+        hexadecimal,
+    ))(input)?;
+    Ok((input, Token::Padding { type_, size }))
+}
+
+/// Parses a `#` literal. Its width is the written one: two digits are a byte, four a short,
+/// so that `#00ff` is a short.
 pub fn immediate(input: &str) -> IResult<&str, Token> {
-    map(
-        nom::sequence::preceded(tag("#"), hexadecimal),
-        |v| {
-            Token::Instruction {
-                opcode: Opcode::LIT,
-                mode: if v > 0xFF {
-                    InstructionMode::Keep | InstructionMode::Short
-                } else {
-                    InstructionMode::Keep
-                },
-                immediate: v,
-            }
+    map_res(
+        preceded(tag("#"), recognize(many1(one_of("0123456789abcdefABCDEF")))),
+        |digits: &str| {
+            let mode = match digits.len() {
+                2 => InstructionMode::Keep,
+                4 => InstructionMode::Keep | InstructionMode::Short,
+                _ => return Err("Immediates need to be a byte or a short"),
+            };
+            u16::from_str_radix(digits, 16)
+                .map(|immediate| Token::Instruction {
+                    opcode: Opcode::LIT,
+                    mode,
+                    immediate,
+                })
+                .or(Err("Could not parse immediate"))
         },
     )(input)
 }
@@ -158,11 +339,1177 @@ pub fn instruction(input: &str) -> IResult<&str, Token> {
                       mode: mode | InstructionMode::Keep,
                       immediate,
                   });
+    // BRK shares the LIT opcode, without the keep flag that makes it a literal. Its flags are
+    // parsed so that `illegal_mode` can reject them with a clear error.
+    let brk = map(preceded(tag("BRK"), instruction_mode_flags), |mode| Token::Instruction {
+        opcode: Opcode::LIT,
+        mode,
+        immediate: 0x00,
+    });
     alt((
+        brk,
         standard_instructions,
         lit))(input)
 }
 
+/// Checks the mode flags of an instruction against the uxn spec, returning why the combination
+/// is illegal. BRK takes no flags: `BRKk` would be a LIT, and `BRK2`/`BRKr` are unassigned.
+pub fn illegal_mode(mnemonic: &str, mode: InstructionMode) -> Option<&'static str> {
+    match mnemonic {
+        "BRK" if mode != InstructionMode::None => Some("BRK does not take mode flags"),
+        _ => None,
+    }
+}
+
+/// The mnemonic of `word` if it looks like an instruction, uppercase letters followed by mode
+/// flags, but isn't one.
+fn unknown_mnemonic(word: &str) -> Option<&str> {
+    let letters = word
+        .find(|c: char| !c.is_ascii_uppercase())
+        .unwrap_or(word.len());
+    let mnemonic = &word[..letters];
+    let is_known = mnemonic == "BRK" || mnemonic.parse::<Opcode>().is_ok();
+    if mnemonic.len() < 2 || is_known || !word[letters..].chars().all(|c| "2kr".contains(c)) {
+        return None;
+    }
+    Some(mnemonic)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + if ca == *cb { 0 } else { 1 };
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// The valid mnemonic closest to `got` by edit distance, the first in opcode order on ties.
+fn closest_mnemonic(got: &str) -> String {
+    let mnemonics = std::iter::once("BRK".to_string())
+        .chain(Opcode::all().iter().map(|opcode| format!("{:?}", opcode)));
+    mnemonics
+        .min_by_key(|mnemonic| edit_distance(got, mnemonic))
+        .unwrap_or_default()
+}
+
+/// Parses a single token, which must be followed by whitespace or the end of input.
+pub fn token(input: &str) -> IResult<&str, Token> {
+    terminated(
+        alt((
+            label,
+            padding,
+            immediate,
+            reference,
+            address,
+            instruction,
+            map(ascii_literal, |s| Token::RawData(s.as_bytes().to_vec())),
+            raw_hex,
+        )),
+        peek(alt((multispace1, eof))),
+    )(input)
+}
+
+/// `[` and `]` are decorative grouping runes, skipped like whitespace.
+fn bracket(input: &str) -> IResult<&str, &str> {
+    terminated(recognize(one_of("[]")), peek(alt((multispace1, eof))))(input)
+}
+
+fn trivia(input: &str) -> IResult<&str, ()> {
+    value(
+        (),
+        many0_count(alt((multispace1, recognize(inline_comment), bracket))),
+    )(input)
+}
+
+/// Location of a token in the source, used for error reporting. Lines and columns start at 1.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    pub len: usize,
+}
+
+/// Maps byte offsets in a source string to line/column spans.
+struct LineIndex<'a> {
+    src: &'a str,
+    starts: Vec<usize>,
+    first_line: usize,
+}
+
+impl<'a> LineIndex<'a> {
+    fn new(src: &'a str, first_line: usize) -> Self {
+        let starts = std::iter::once(0)
+            .chain(src.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        LineIndex {
+            src,
+            starts,
+            first_line,
+        }
+    }
+
+    fn span(&self, offset: usize, len: usize) -> Span {
+        let line = self.starts.partition_point(|&start| start <= offset);
+        let start = self.starts[line - 1];
+        Span {
+            line: self.first_line + line - 1,
+            column: self.src[start..offset].chars().count() + 1,
+            len: self.src[offset..offset + len].chars().count(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum AsmError {
+    Syntax { at: Span, token: String },
+    UnknownLabel { at: Span, name: String },
+    DuplicateLabel { at: Span, name: String },
+    MissingParent { at: Span, name: String },
+    NotZeroPage { at: Span, name: String, address: u16 },
+    RelativeOutOfRange { at: Span, name: String, offset: i32 },
+    IllegalMode { at: Span, instruction: String, reason: &'static str },
+    ZeroPageWrite { at: Span },
+    MemoryOverflow { at: Span },
+    Io { at: Span, message: String },
+    RecursiveMacro { at: Span, name: String },
+    UnclosedMacro { at: Span, name: String },
+    UnknownOpcode { at: Span, got: String, suggestion: String },
+    UnclosedBlock { at: Span },
+    /// Bare hex outside of data, in [`assemble_strict`].
+    AmbiguousData { at: Span, token: String },
+}
+
+impl AsmError {
+    pub fn span(&self) -> Span {
+        match self {
+            AsmError::Syntax { at, .. }
+            | AsmError::UnknownLabel { at, .. }
+            | AsmError::DuplicateLabel { at, .. }
+            | AsmError::MissingParent { at, .. }
+            | AsmError::NotZeroPage { at, .. }
+            | AsmError::RelativeOutOfRange { at, .. }
+            | AsmError::IllegalMode { at, .. }
+            | AsmError::ZeroPageWrite { at }
+            | AsmError::MemoryOverflow { at }
+            | AsmError::Io { at, .. }
+            | AsmError::RecursiveMacro { at, .. }
+            | AsmError::UnclosedMacro { at, .. }
+            | AsmError::UnknownOpcode { at, .. }
+            | AsmError::UnclosedBlock { at }
+            | AsmError::AmbiguousData { at, .. } => *at,
+        }
+    }
+
+    /// Renders the error together with the offending source line and a caret under the token:
+    ///
+    /// ```text
+    /// error: unknown label `mian`
+    ///  --> hello.tal:3:2
+    ///   |
+    /// 3 | ;mian JMP2
+    ///   | ^^^^^
+    /// ```
+    pub fn render(&self, name: &str, src: &str) -> String {
+        let at = self.span();
+        let line = src.lines().nth(at.line - 1).unwrap_or("");
+        // keep tabs so that the caret lines up with the source line
+        let indent: String = line
+            .chars()
+            .take(at.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let width = line.chars().count().saturating_sub(at.column - 1);
+        let gutter = " ".repeat(at.line.to_string().len());
+        format!(
+            "error: {}\n{}--> {}:{}:{}\n{} |\n{} | {}\n{} | {}{}\n",
+            self,
+            gutter,
+            name,
+            at.line,
+            at.column,
+            gutter,
+            at.line,
+            line,
+            gutter,
+            indent,
+            "^".repeat(at.len.min(width).max(1)),
+        )
+    }
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AsmError::Syntax { token, .. } => write!(f, "unrecognized token `{}`", token),
+            AsmError::UnknownLabel { name, .. } => write!(f, "unknown label `{}`", name),
+            AsmError::DuplicateLabel { name, .. } => write!(f, "duplicate label `{}`", name),
+            AsmError::MissingParent { name, .. } => {
+                write!(f, "sublabel `&{}` is not inside a parent label", name)
+            }
+            AsmError::NotZeroPage { name, address, .. } => write!(
+                f,
+                "`{}` is at {:#06x}, outside of the zero page",
+                name, address
+            ),
+            AsmError::RelativeOutOfRange { name, offset, .. } => write!(
+                f,
+                "`{}` is {} bytes away, too far for a relative reference",
+                name, offset
+            ),
+            AsmError::IllegalMode {
+                instruction,
+                reason,
+                ..
+            } => write!(f, "illegal instruction `{}`: {}", instruction, reason),
+            AsmError::ZeroPageWrite { .. } => write!(f, "cannot write bytes into the zero page"),
+            AsmError::MemoryOverflow { .. } => write!(f, "program does not fit in memory"),
+            AsmError::Io { message, .. } => write!(f, "could not read source: {}", message),
+            AsmError::RecursiveMacro { name, .. } => {
+                write!(f, "macro `{}` expands to itself", name)
+            }
+            AsmError::UnclosedMacro { name, .. } => {
+                write!(f, "macro `{}` is missing its closing `}}`", name)
+            }
+            AsmError::UnknownOpcode {
+                got, suggestion, ..
+            } => write!(
+                f,
+                "unknown instruction `{}`, did you mean `{}`?",
+                got, suggestion
+            ),
+            AsmError::UnclosedBlock { .. } => write!(f, "block is missing its closing `}}`"),
+            AsmError::AmbiguousData { token, .. } => write!(
+                f,
+                "raw data `{}` outside of data, label it or write it as a literal",
+                token
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// Code that assembles but is likely to be wrong, reported by [`check_stack_depth`].
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum AsmWarning {
+    /// The instruction pops more bytes than were pushed since the start of its block, leaving
+    /// the working stack `depth` bytes deep.
+    PossibleUnderflow { at: Span, depth: i32 },
+}
+
+impl AsmWarning {
+    pub fn span(&self) -> Span {
+        match self {
+            AsmWarning::PossibleUnderflow { at, .. } => *at,
+        }
+    }
+}
+
+impl fmt::Display for AsmWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AsmWarning::PossibleUnderflow { depth, .. } => {
+                write!(f, "possible stack underflow, {} bytes short", -depth)
+            }
+        }
+    }
+}
+
+/// The output of the assembler: the ROM image starting at [`PAGE_PROGRAM`] and the label table.
+#[derive(Debug, Clone)]
+pub struct Assembled {
+    pub rom: Vec<u8>,
+    pub symbols: Vec<(u16, String)>,
+    emitted: Vec<Emitted>,
+}
+
+/// What [`assemble_into`] returns besides the bytes: the address the fragment starts at and its
+/// label table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssembledMeta {
+    pub origin: u16,
+    pub symbols: Vec<(u16, String)>,
+}
+
+/// Bytes written by one token, for the listing.
+#[derive(Debug, Clone)]
+struct Emitted {
+    at: Span,
+    address: u16,
+    bytes: Vec<u8>,
+    source: String,
+}
+
+// the listing only records where the bytes came from, two ROMs are the same if they have the
+// same bytes and labels
+impl PartialEq for Assembled {
+    fn eq(&self, other: &Self) -> bool {
+        self.rom == other.rom && self.symbols == other.symbols
+    }
+}
+
+impl Eq for Assembled {}
+
+impl Assembled {
+    /// A listing of the emitted bytes, one line per source line that emitted any:
+    /// `ADDR: BYTES    ; source`. The source is the line's tokens, without comments.
+    pub fn listing(&self) -> String {
+        let mut lines: Vec<(usize, u16, Vec<u8>, Vec<&str>)> = vec![];
+        for emitted in &self.emitted {
+            match lines.last_mut() {
+                Some((line, _, bytes, source)) if *line == emitted.at.line => {
+                    bytes.extend(&emitted.bytes);
+                    source.push(&emitted.source);
+                }
+                _ => lines.push((
+                    emitted.at.line,
+                    emitted.address,
+                    emitted.bytes.clone(),
+                    vec![&emitted.source],
+                )),
+            }
+        }
+
+        let mut out = String::new();
+        for (_, address, bytes, source) in lines {
+            let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+            out.push_str(&format!(
+                "{:04x}: {:<24} ; {}\n",
+                address,
+                hex.join(" "),
+                source.join(" ")
+            ));
+        }
+        out
+    }
+
+    /// Serializes the symbols in the `.sym` format written by uxnasm: for each label,
+    /// a big-endian address followed by the NUL-terminated name.
+    pub fn symbol_file(&self) -> Vec<u8> {
+        let mut out = vec![];
+        for (address, name) in &self.symbols {
+            out.extend_from_slice(&address.to_be_bytes());
+            out.extend_from_slice(name.as_bytes());
+            out.push(0);
+        }
+        out
+    }
+}
+
+/// How [`write_output`] writes a ROM.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum OutputFormat {
+    /// The raw bytes, as loaded at [`PAGE_PROGRAM`].
+    Binary,
+    /// Intel HEX records of up to 16 bytes, addressed from [`PAGE_PROGRAM`], for flashing
+    /// tools.
+    IntelHex,
+}
+
+fn hex_record(
+    out: &mut impl Write,
+    address: u16,
+    type_: u8,
+    data: &[u8],
+) -> std::io::Result<()> {
+    let [high, low] = address.to_be_bytes();
+    let mut record = vec![data.len() as u8, high, low, type_];
+    record.extend_from_slice(data);
+    let sum = record.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+    record.push(sum.wrapping_neg());
+    let hex: String = record.iter().map(|b| format!("{:02X}", b)).collect();
+    writeln!(out, ":{}", hex)
+}
+
+/// Writes the ROM of `assembled` to `out` in `format`.
+pub fn write_output<W: Write>(
+    assembled: &Assembled,
+    format: OutputFormat,
+    out: &mut W,
+) -> std::io::Result<()> {
+    match format {
+        OutputFormat::Binary => out.write_all(&assembled.rom),
+        OutputFormat::IntelHex => {
+            for (i, chunk) in assembled.rom.chunks(16).enumerate() {
+                hex_record(out, PAGE_PROGRAM + i as u16 * 16, 0x00, chunk)?;
+            }
+            // end of file
+            hex_record(out, 0, 0x01, &[])
+        }
+    }
+}
+
+/// A token of the source as written, for editors: unlike when assembling, comments and
+/// brackets are kept and macros are not expanded.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum SourceToken {
+    Token(Token),
+    /// A `( comment )`, including its parentheses.
+    Comment(String),
+    /// One of the `[ ] { }` runes.
+    Bracket(char),
+    /// The `%name` starting a macro definition.
+    MacroDefinition(String),
+    /// The use of a macro defined earlier in the source.
+    MacroCall(String),
+}
+
+/// Splits `src` into [`SourceToken`]s with their location in the source.
+pub fn tokenize(src: &str) -> Result<Vec<(Span, SourceToken)>, AsmError> {
+    let lines = LineIndex::new(src, 1);
+    let span = |offset, len| lines.span(offset, len);
+    let mut macros = vec![];
+    let mut blocks = 0;
+    let mut tokens = vec![];
+    let mut input = src.trim_start();
+    while !input.is_empty() {
+        let offset = src.len() - input.len();
+        let word_len = input.find(char::is_whitespace).unwrap_or(input.len());
+        let word = &input[..word_len];
+
+        let (len, token) = if let Ok((rest, _)) = inline_comment::<nom::error::Error<&str>>(input) {
+            let len = input.len() - rest.len();
+            (len, SourceToken::Comment(input[..len].to_string()))
+        } else if let Some(mode) = block_mode(word) {
+            let label = format!("{{{}", blocks);
+            blocks += 1;
+            (word_len, SourceToken::Token(Token::Reference { mode, label }))
+        } else if matches!(word, "[" | "]" | "{" | "}") {
+            (1, SourceToken::Bracket(word.as_bytes()[0] as char))
+        } else if let Some(name) = word.strip_prefix('%') {
+            macros.push(name);
+            (word_len, SourceToken::MacroDefinition(name.to_string()))
+        } else if macros.contains(&word) {
+            (word_len, SourceToken::MacroCall(word.to_string()))
+        } else {
+            let (rest, (at, token)) = next_token(input, offset, word_len, &span)?;
+            tokens.push((at, SourceToken::Token(token)));
+            input = rest.trim_start();
+            continue;
+        };
+        tokens.push((span(offset, len), token));
+        input = input[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+/// Splits `src`, which starts at line `first_line` of the source, into tokens.
+fn lex(src: &str, first_line: usize) -> Result<Vec<(Span, Token)>, AsmError> {
+    Lexer::default().lex(src, first_line)
+}
+
+/// Whether bare hex after `previous` is clearly data: it follows a label, or is part of a run
+/// of data such as a string or a jump table.
+fn follows_data(previous: Option<&Token>) -> bool {
+    match previous {
+        Some(Token::Label { type_, .. }) => *type_ != LabelType::Anonymous,
+        Some(Token::RawData(_)) => true,
+        Some(Token::Address { mode, .. } | Token::Reference { mode, .. }) => {
+            *mode == AddressingMode::RawAbsolute
+        }
+        _ => false,
+    }
+}
+
+/// Parses the token at the start of `input`, at byte `offset` of the source, returning the rest
+/// of the input.
+fn next_token<'a>(
+    input: &'a str,
+    offset: usize,
+    word_len: usize,
+    span: &dyn Fn(usize, usize) -> Span,
+) -> Result<(&'a str, (Span, Token)), AsmError> {
+    match token(input) {
+        Ok((rest, token)) => {
+            let len = input.len() - rest.len();
+            if let Token::Instruction { mode, .. } = token {
+                if let Some(reason) = illegal_mode(input.get(..3).unwrap_or(""), mode) {
+                    return Err(AsmError::IllegalMode {
+                        at: span(offset, len),
+                        instruction: input[..len].to_string(),
+                        reason,
+                    });
+                }
+            }
+            Ok((rest, (span(offset, len), token)))
+        }
+        Err(_) => {
+            let at = span(offset, word_len);
+            let word = &input[..word_len];
+            Err(match unknown_mnemonic(word) {
+                Some(got) => AsmError::UnknownOpcode {
+                    at,
+                    got: got.to_string(),
+                    suggestion: closest_mnemonic(got),
+                },
+                None => AsmError::Syntax {
+                    at,
+                    token: word.to_string(),
+                },
+            })
+        }
+    }
+}
+
+/// Splits source into tokens, expanding macros. Macros stay defined across calls to
+/// [`Lexer::lex`], so that source can be lexed in chunks.
+#[derive(Default)]
+struct Lexer {
+    macros: HashMap<String, String>,
+    // the macros being expanded, innermost last
+    expanding: Vec<String>,
+    // number of macro expansions so far, to give labels in each one unique names
+    expansions: usize,
+    // the end labels of the open `?{ }` and `!{ }` blocks, innermost last
+    blocks: Vec<String>,
+    // number of blocks so far, to give each end label a unique name
+    anonymous: usize,
+    // only accept bare hex as data, see `assemble_strict`
+    strict: bool,
+}
+
+impl Lexer {
+    fn lex(&mut self, src: &str, first_line: usize) -> Result<Vec<(Span, Token)>, AsmError> {
+        let lines = LineIndex::new(src, first_line);
+        let mut tokens = vec![];
+        // a failed chunk may be lexed again with more source, see `assemble_reader`
+        let counters = (self.blocks.clone(), self.anonymous, self.expansions);
+        let result = self.lex_into(src, &|offset, len| lines.span(offset, len), &mut tokens);
+        if result.is_err() {
+            (self.blocks, self.anonymous, self.expansions) = counters;
+        }
+        result.map(|_| tokens)
+    }
+
+    /// Lexes `src` into `tokens`, locating tokens with `span`. Tokens expanded from a macro are
+    /// located at the macro's name.
+    fn lex_into(
+        &mut self,
+        src: &str,
+        span: &dyn Fn(usize, usize) -> Span,
+        tokens: &mut Vec<(Span, Token)>,
+    ) -> Result<(), AsmError> {
+        let mut input = src;
+        loop {
+            input = trivia(input).map_or(input, |(rest, _)| rest);
+            if input.is_empty() {
+                return Ok(());
+            }
+            let offset = src.len() - input.len();
+            let word_len = input.find(char::is_whitespace).unwrap_or(input.len());
+            let word = &input[..word_len];
+
+            if let Some(name) = word.strip_prefix('%') {
+                input = self.define_macro(name, &input[word_len..], span(offset, word_len))?;
+                continue;
+            }
+            if let Some(mode) = block_mode(word) {
+                let label = format!("{{{}", self.anonymous);
+                self.anonymous += 1;
+                self.blocks.push(label.clone());
+                tokens.push((span(offset, word_len), Token::Reference { mode, label }));
+                input = &input[word_len..];
+                continue;
+            }
+            if word == "}" {
+                let at = span(offset, word_len);
+                let name = self.blocks.pop().ok_or_else(|| AsmError::Syntax {
+                    at,
+                    token: word.to_string(),
+                })?;
+                let type_ = LabelType::Anonymous;
+                tokens.push((at, Token::Label { name, type_ }));
+                input = &input[word_len..];
+                continue;
+            }
+            if let Some(body) = self.macros.get(word) {
+                let at = span(offset, word_len);
+                if self.expanding.iter().any(|name| name == word) {
+                    return Err(AsmError::RecursiveMacro {
+                        at,
+                        name: word.to_string(),
+                    });
+                }
+                let body = body.clone();
+                self.expanding.push(word.to_string());
+                let mut expanded = vec![];
+                let result = self.lex_into(&body, &|_, _| at, &mut expanded);
+                self.expanding.pop();
+                result?;
+                self.expansions += 1;
+                tokens.extend(rename_local_labels(expanded, self.expansions));
+                input = &input[word_len..];
+                continue;
+            }
+
+            let (rest, token) = next_token(input, offset, word_len, span)?;
+            let bare_hex = matches!(token.1, Token::RawData(_)) && !word.starts_with('"');
+            if self.strict && bare_hex && !follows_data(tokens.last().map(|(_, t)| t)) {
+                return Err(AsmError::AmbiguousData {
+                    at: token.0,
+                    token: word.to_string(),
+                });
+            }
+            tokens.push(token);
+            input = rest;
+        }
+    }
+
+    /// Defines macro `name` from the `{ body }` at the start of `input`, returning the rest.
+    fn define_macro<'a>(
+        &mut self,
+        name: &str,
+        input: &'a str,
+        at: Span,
+    ) -> Result<&'a str, AsmError> {
+        let body = trivia(input).map_or(input, |(rest, _)| rest);
+        let body = match body.strip_prefix('{') {
+            Some(body) if !name.is_empty() && body.chars().next().is_none_or(char::is_whitespace) => {
+                body
+            }
+            _ => {
+                return Err(AsmError::Syntax {
+                    at,
+                    token: format!("%{}", name),
+                })
+            }
+        };
+
+        let mut depth = 1;
+        let mut rest = body;
+        loop {
+            rest = trivia(rest).map_or(rest, |(rest, _)| rest);
+            if rest.is_empty() {
+                return Err(AsmError::UnclosedMacro {
+                    at,
+                    name: name.to_string(),
+                });
+            }
+            let len = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            match &rest[..len] {
+                "{" => depth += 1,
+                "}" if depth == 1 => break,
+                "}" => depth -= 1,
+                _ => {}
+            }
+            rest = &rest[len..];
+        }
+
+        let body = &body[..body.len() - rest.len()];
+        self.macros.insert(name.to_string(), body.to_string());
+        Ok(&rest[1..])
+    }
+}
+
+/// The jump opening a `?{ }` or `!{ }` block, which skips to its end.
+fn block_mode(word: &str) -> Option<AddressingMode> {
+    match word {
+        "?{" => Some(AddressingMode::ImmediateConditional),
+        "!{" => Some(AddressingMode::ImmediateJump),
+        _ => None,
+    }
+}
+
+/// Suffixes the labels defined in a macro expansion, and the references to them, with
+/// `~expansion` so that expanding the same macro twice doesn't define a label twice.
+fn rename_local_labels(mut tokens: Vec<(Span, Token)>, expansion: usize) -> Vec<(Span, Token)> {
+    let mut parents = vec![];
+    let mut children = vec![];
+    for (_, token) in &tokens {
+        match token {
+            Token::Label {
+                name,
+                type_: LabelType::Parent,
+            } => parents.push(name.clone()),
+            Token::Label {
+                name,
+                type_: LabelType::Child,
+            } => children.push(name.clone()),
+            _ => {}
+        }
+    }
+    let local = |name: &str| format!("{}~{}", name, expansion);
+
+    for (_, token) in &mut tokens {
+        match token {
+            // block end labels are unique already
+            Token::Label {
+                type_: LabelType::Anonymous,
+                ..
+            } => {}
+            Token::Label { name, .. } => *name = local(name),
+            Token::Reference { label, .. } => {
+                if let Some(child) = label.strip_prefix('&') {
+                    if children.iter().any(|c| c == child) {
+                        *label = format!("&{}", local(child));
+                    }
+                } else {
+                    let (parent, child) = label.split_once('/').unwrap_or((label, ""));
+                    if parents.iter().any(|p| p == parent) {
+                        let parent = local(parent);
+                        *label = match child {
+                            "" => parent,
+                            child if children.iter().any(|c| c == child) => {
+                                format!("{}/{}", parent, local(child))
+                            }
+                            child => format!("{}/{}", parent, child),
+                        };
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    tokens
+}
+
+fn lit_byte(short: bool) -> u8 {
+    let mode = if short {
+        InstructionMode::Keep | InstructionMode::Short
+    } else {
+        InstructionMode::Keep
+    };
+    encode_byte(Opcode::LIT, mode)
+}
+
+fn token_size(token: &Token) -> usize {
+    match token {
+        Token::Instruction {
+            opcode: Opcode::LIT,
+            mode,
+            ..
+        } if mode.is_keep() => {
+            if mode.is_short() {
+                3
+            } else {
+                2
+            }
+        }
+        Token::Instruction { .. } => 1,
+        Token::Address { mode, .. } | Token::Reference { mode, .. } => match mode {
+            AddressingMode::LiteralAbsolute
+            | AddressingMode::ImmediateConditional
+            | AddressingMode::ImmediateJump => 3,
+            _ => 2,
+        },
+        Token::RawData(bytes) => bytes.len(),
+        Token::Label { .. } | Token::Padding { .. } => 0,
+    }
+}
+
+/// Tracks the current parent label so that `&child` names can be expanded to `parent/child`.
+#[derive(Default)]
+struct Scope {
+    parent: Option<String>,
+}
+
+impl Scope {
+    fn define(&mut self, name: &str, type_: LabelType, at: Span) -> Result<String, AsmError> {
+        match type_ {
+            LabelType::Parent => {
+                self.parent = Some(name.to_string());
+                Ok(name.to_string())
+            }
+            LabelType::Child => self.child(name, at),
+            LabelType::Anonymous => Ok(name.to_string()),
+        }
+    }
+
+    fn resolve(&self, label: &str, at: Span) -> Result<String, AsmError> {
+        match label.strip_prefix('&') {
+            Some(child) => self.child(child, at),
+            None => Ok(label.to_string()),
+        }
+    }
+
+    fn child(&self, name: &str, at: Span) -> Result<String, AsmError> {
+        match &self.parent {
+            Some(parent) => Ok(format!("{}/{}", parent, name)),
+            None => Err(AsmError::MissingParent {
+                at,
+                name: name.to_string(),
+            }),
+        }
+    }
+}
+
+/// Applies a padding rune to the current address.
+fn pad(address: usize, type_: PaddingType, size: u16) -> usize {
+    match type_ {
+        PaddingType::Absolute => size as usize,
+        PaddingType::Relative => address + size as usize,
+    }
+}
+
+/// First pass: walks the tokens to compute the address of every label.
+fn collect_labels(tokens: &[(Span, Token)]) -> Result<Vec<(u16, String)>, AsmError> {
+    let mut scope = Scope::default();
+    let mut labels: Vec<(u16, String)> = vec![];
+    let mut address = 0usize;
+    for (at, token) in tokens {
+        match token {
+            Token::Label { name, type_ } => {
+                let name = scope.define(name, *type_, *at)?;
+                if labels.iter().any(|(_, l)| *l == name) {
+                    return Err(AsmError::DuplicateLabel { at: *at, name });
+                }
+                labels.push((address as u16, name));
+            }
+            Token::Padding { type_, size } => address = pad(address, *type_, *size),
+            _ => address += token_size(token),
+        }
+        if address > 0x10000 {
+            return Err(AsmError::MemoryOverflow { at: *at });
+        }
+    }
+    Ok(labels)
+}
+
+fn encode_address(mode: AddressingMode, address: u16) -> Vec<u8> {
+    match mode {
+        AddressingMode::LiteralRelative | AddressingMode::LiteralZeroPage => {
+            vec![lit_byte(false), address as u8]
+        }
+        AddressingMode::RawAbsolute => address.to_be_bytes().to_vec(),
+        AddressingMode::LiteralAbsolute => {
+            let [high, low] = address.to_be_bytes();
+            vec![lit_byte(true), high, low]
+        }
+        AddressingMode::ImmediateConditional | AddressingMode::ImmediateJump => {
+            let [high, low] = address.to_be_bytes();
+            let instr = if mode == AddressingMode::ImmediateConditional { JCI } else { JMI };
+            vec![instr, high, low]
+        }
+    }
+}
+
+/// Encodes `token`, located at `here`, into its bytes.
+fn encode(
+    at: Span,
+    here: usize,
+    token: &Token,
+    scope: &Scope,
+    labels: &HashMap<&str, u16>,
+) -> Result<Vec<u8>, AsmError> {
+    Ok(match token {
+        Token::Instruction {
+            opcode,
+            mode,
+            immediate,
+        } => {
+            let mut bytes = vec![encode_byte(*opcode, *mode)];
+            if token_size(token) == 3 {
+                bytes.extend_from_slice(&immediate.to_be_bytes());
+            } else if token_size(token) == 2 {
+                bytes.push(*immediate as u8);
+            }
+            bytes
+        }
+        Token::Address { mode, address } => encode_address(*mode, *address),
+        Token::Reference { mode, label } => {
+            let name = scope.resolve(label, at)?;
+            let address = *labels.get(name.as_str()).ok_or_else(|| {
+                if is_anonymous(&name) {
+                    AsmError::UnclosedBlock { at }
+                } else {
+                    AsmError::UnknownLabel {
+                        at,
+                        name: name.clone(),
+                    }
+                }
+            })?;
+            match mode {
+                AddressingMode::LiteralZeroPage if address > 0xff => {
+                    return Err(AsmError::NotZeroPage { at, name, address });
+                }
+                AddressingMode::LiteralRelative => {
+                    // relative to the PC after the LIT and the jump instruction following it
+                    let offset = address as i32 - (here as i32 + 3);
+                    if !(-128..=127).contains(&offset) {
+                        return Err(AsmError::RelativeOutOfRange { at, name, offset });
+                    }
+                    encode_address(*mode, offset as i8 as u8 as u16)
+                }
+                AddressingMode::ImmediateConditional | AddressingMode::ImmediateJump => {
+                    // relative to the PC after the instruction and its operand
+                    let offset = address.wrapping_sub(here as u16 + 3);
+                    encode_address(*mode, offset)
+                }
+                _ => encode_address(*mode, address),
+            }
+        }
+        Token::RawData(bytes) => bytes.clone(),
+        Token::Label { .. } | Token::Padding { .. } => vec![],
+    })
+}
+
+/// Second pass: emits the bytes of every token into `rom`, a ROM image starting at
+/// [`PAGE_PROGRAM`], resolving label references. `address` is the logical address, which
+/// paddings below [`PAGE_PROGRAM`] move without emitting anything; a byte's offset in `rom` is
+/// its address less [`PAGE_PROGRAM`]. A relative padding in the program reserves its bytes as
+/// zeros, even at the end of the ROM.
+fn emit(
+    tokens: &[(Span, Token)],
+    symbols: &[(u16, String)],
+    rom: &mut Vec<u8>,
+) -> Result<Vec<Emitted>, AsmError> {
+    let labels: HashMap<&str, u16> = symbols
+        .iter()
+        .map(|(address, name)| (name.as_str(), *address))
+        .collect();
+    let mut scope = Scope::default();
+    let mut address = 0usize;
+    let mut emitted = vec![];
+    for (at, token) in tokens {
+        match token {
+            Token::Label { name, type_ } => {
+                scope.define(name, *type_, *at)?;
+            }
+            Token::Padding { type_, size } => {
+                address = pad(address, *type_, *size);
+                let end = address.saturating_sub(PAGE_PROGRAM as usize);
+                if *type_ == PaddingType::Relative && rom.len() < end {
+                    rom.resize(end, 0);
+                }
+            }
+            _ => {
+                let bytes = encode(*at, address, token, &scope, &labels)?;
+                if address < PAGE_PROGRAM as usize {
+                    return Err(AsmError::ZeroPageWrite { at: *at });
+                }
+                let offset = address - PAGE_PROGRAM as usize;
+                if rom.len() < offset + bytes.len() {
+                    rom.resize(offset + bytes.len(), 0);
+                }
+                rom[offset..offset + bytes.len()].copy_from_slice(&bytes);
+                emitted.push(Emitted {
+                    at: *at,
+                    address: address as u16,
+                    source: token.to_string(),
+                    bytes,
+                });
+                address += emitted.last().map_or(0, |e| e.bytes.len());
+            }
+        }
+    }
+    Ok(emitted)
+}
+
+/// Assembles uxntal source into a ROM.
+pub fn assemble(src: &str) -> Result<Assembled, AsmError> {
+    assemble_tokens(&lex(src, 1)?)
+}
+
+/// Like [`assemble`], but rejects bare hex such as `add2` unless it clearly is data: right
+/// after a label, or after other data. Catches typos that would otherwise assemble into data in
+/// the middle of code.
+pub fn assemble_strict(src: &str) -> Result<Assembled, AsmError> {
+    let mut lexer = Lexer {
+        strict: true,
+        ..Lexer::default()
+    };
+    assemble_tokens(&lexer.lex(src, 1)?)
+}
+
+/// Like [`assemble`], but starts at [`PAGE_PROGRAM`] so that the source doesn't need a `|0100`.
+pub fn assemble_program(src: &str) -> Result<Assembled, AsmError> {
+    let start = Token::Padding {
+        type_: PaddingType::Absolute,
+        size: PAGE_PROGRAM,
+    };
+    let start_span = Span {
+        line: 1,
+        column: 1,
+        len: 0,
+    };
+    let mut tokens = vec![(start_span, start)];
+    tokens.extend(lex(src, 1)?);
+    assemble_tokens(&tokens)
+}
+
+/// Assembles `src` onto the end of `out`, a ROM image starting at [`PAGE_PROGRAM`]. The source
+/// starts at the address following the bytes already in `out`, so that code generators can
+/// emit a program in several fragments. Labels are not shared between fragments.
+pub fn assemble_into(src: &str, out: &mut Vec<u8>) -> Result<AssembledMeta, AsmError> {
+    let start_span = Span {
+        line: 1,
+        column: 1,
+        len: 0,
+    };
+    let origin = u16::try_from(PAGE_PROGRAM as usize + out.len())
+        .map_err(|_| AsmError::MemoryOverflow { at: start_span })?;
+    let start = Token::Padding {
+        type_: PaddingType::Absolute,
+        size: origin,
+    };
+    let mut tokens = vec![(start_span, start)];
+    tokens.extend(lex(src, 1)?);
+    let symbols = collect_labels(&tokens)?;
+    emit(&tokens, &symbols, out)?;
+    Ok(AssembledMeta { origin, symbols })
+}
+
+/// Assembles source read line by line from `reader`, e.g. a large file or stdin.
+pub fn assemble_reader<R: BufRead>(reader: R) -> Result<Assembled, AsmError> {
+    let mut lexer = Lexer::default();
+    let mut tokens = vec![];
+    // lines that can't be tokenized on their own yet, e.g. the start of a multi-line comment
+    let mut pending = String::new();
+    let mut pending_line = 1;
+    // how many more parens and braces `pending` opens than it closes
+    let mut open = (0isize, 0isize);
+    for (i, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| AsmError::Io {
+            at: Span {
+                line: i + 1,
+                column: 1,
+                len: 0,
+            },
+            message: e.to_string(),
+        })?;
+        let retrying = !pending.is_empty();
+        if !retrying {
+            pending_line = i + 1;
+        }
+        pending.push_str(&line);
+        pending.push('\n');
+        for c in line.chars() {
+            match c {
+                '(' => open.0 += 1,
+                ')' => open.0 -= 1,
+                '{' => open.1 += 1,
+                '}' => open.1 -= 1,
+                _ => {}
+            }
+        }
+        // a comment or macro left open can't be complete before its brackets are closed, so
+        // wait for them instead of lexing the pending lines again on every line
+        if retrying && (open.0 > 0 || open.1 > 0) {
+            continue;
+        }
+        match lexer.lex(&pending, pending_line) {
+            Ok(chunk) => {
+                tokens.extend(chunk);
+                pending.clear();
+                open = (0, 0);
+            }
+            // a comment or a `LIT` operand may continue on the next line
+            Err(AsmError::Syntax { at, .. }) if at.line == i + 1 => {}
+            Err(AsmError::UnclosedMacro { .. }) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    tokens.extend(lexer.lex(&pending, pending_line)?);
+    assemble_tokens(&tokens)
+}
+
+/// Tracks the working-stack depth through `src`, warning about instructions that pop from an
+/// empty stack. The stack is only known to be empty after a padding, where a vector such as
+/// `|0100` starts. A label may be jumped to or called with arguments, and a call may leave
+/// results, so the depth is unknown from there on until the next padding, and popping the
+/// values that were there is fine.
+pub fn check_stack_depth(src: &str) -> Result<Vec<AsmWarning>, AsmError> {
+    Ok(stack_warnings(&lex(src, 1)?))
+}
+
+fn stack_warnings(tokens: &[(Span, Token)]) -> Vec<AsmWarning> {
+    let mut warnings = vec![];
+    // `None` while the depth at the start of the block is unknown
+    let mut depth = Some(0i32);
+    let mut pop = |depth: &mut Option<i32>, at: Span, bytes: i32| {
+        if let Some(d) = depth {
+            *d -= bytes;
+            if *d < 0 {
+                warnings.push(AsmWarning::PossibleUnderflow { at, depth: *d });
+                *d = 0;
+            }
+        }
+    };
+    for (at, token) in tokens {
+        match token {
+            Token::Padding { .. } => depth = Some(0),
+            Token::Label { .. } => depth = None,
+            Token::Reference {
+                mode: mode @ (AddressingMode::ImmediateConditional | AddressingMode::ImmediateJump),
+                ..
+            } => {
+                // `JCI` pops its condition and falls through, `JMI` leaves the block
+                if *mode == AddressingMode::ImmediateConditional {
+                    pop(&mut depth, *at, 1);
+                } else {
+                    depth = None;
+                }
+            }
+            Token::Address { mode, .. } | Token::Reference { mode, .. } => {
+                let pushed = match mode {
+                    AddressingMode::LiteralAbsolute => 2,
+                    AddressingMode::RawAbsolute => 0,
+                    _ => 1,
+                };
+                depth = depth.map(|d| d + pushed);
+            }
+            Token::RawData(_) => {}
+            Token::Instruction { opcode, mode, .. } => {
+                let (pops, pushes) = if !mode.is_return() {
+                    opcode.stack_effect(*mode)
+                } else if *opcode == Opcode::STH {
+                    // STHr moves from the return stack onto the working stack
+                    (0, if mode.is_short() { 2 } else { 1 })
+                } else {
+                    (0, 0)
+                };
+                pop(&mut depth, *at, pops as i32);
+                depth = depth.map(|d| d + pushes as i32);
+
+                // the code after a `BRK` or a jump is only reached through a label, and the
+                // code after a call runs with whatever the callee left
+                let brk = *opcode == Opcode::LIT && !mode.is_keep();
+                if brk || matches!(opcode, Opcode::JMP | Opcode::JSR) {
+                    depth = None;
+                }
+            }
+        }
+    }
+    warnings
+}
+
+/// Assembles several source files, in order, into one ROM. The files share one label table, so
+/// any file can reference labels defined in another.
+pub fn assemble_project(files: &[PathBuf]) -> Result<Assembled, AsmError> {
+    let mut lexer = Lexer::default();
+    let mut tokens = vec![];
+    for path in files {
+        let src = std::fs::read_to_string(path).map_err(|e| AsmError::Io {
+            at: Span {
+                line: 1,
+                column: 1,
+                len: 0,
+            },
+            message: format!("{}: {}", path.display(), e),
+        })?;
+        tokens.extend(lexer.lex(&src, 1)?);
+    }
+    assemble_tokens(&tokens)
+}
+
+fn assemble_tokens(tokens: &[(Span, Token)]) -> Result<Assembled, AsmError> {
+    let symbols = collect_labels(tokens)?;
+    let mut rom = vec![];
+    let emitted = emit(tokens, &symbols, &mut rom)?;
+    Ok(Assembled {
+        rom,
+        symbols,
+        emitted,
+    })
+}
+
 #[test]
 fn parse_either_or() {
     let result: IResult<&str, u32> = either_or(1, 0, char('1'))("1");
@@ -213,6 +1560,16 @@ fn parse_immediate() {
             }
         ))
     );
+    assert_eq!(
+        assemble("|0100 #ff #00ff").unwrap().rom,
+        vec![0x80, 0xff, 0xa0, 0x00, 0xff]
+    );
+    for src in ["#f", "#fff", "#00fff"] {
+        assert!(matches!(
+            assemble(&format!("|0100 {}", src)),
+            Err(AsmError::Syntax { .. })
+        ));
+    }
 }
 
 #[test]
@@ -289,3 +1646,491 @@ fn parse_instruction() {
         ))
     );
 }
+
+#[test]
+fn assemble_labels_and_padding() {
+    let assembled = assemble(
+        "|00 @System &vector $2 &wst $1
+         |0100 @main ;main/loop .System/wst &loop LIT2 abcd BRK",
+    )
+    .unwrap();
+    assert_eq!(
+        assembled.symbols,
+        vec![
+            (0x0000, "System".to_string()),
+            (0x0000, "System/vector".to_string()),
+            (0x0002, "System/wst".to_string()),
+            (0x0100, "main".to_string()),
+            (0x0105, "main/loop".to_string()),
+        ]
+    );
+    assert_eq!(
+        assembled.rom,
+        vec![0xa0, 0x01, 0x05, 0x80, 0x02, 0xa0, 0xab, 0xcd, 0x00]
+    );
+}
+
+#[test]
+fn assemble_errors() {
+    assert_eq!(
+        assemble("|0100 ;nope"),
+        Err(AsmError::UnknownLabel {
+            at: Span {
+                line: 1,
+                column: 7,
+                len: 5
+            },
+            name: "nope".to_string()
+        })
+    );
+    assert_eq!(
+        assemble("@a\n@a"),
+        Err(AsmError::DuplicateLabel {
+            at: Span {
+                line: 2,
+                column: 1,
+                len: 2
+            },
+            name: "a".to_string()
+        })
+    );
+    assert!(matches!(
+        assemble("|0100 DUP2x"),
+        Err(AsmError::Syntax { token, .. }) if token == "DUP2x"
+    ));
+    assert!(matches!(
+        assemble("|0100 @far .far"),
+        Err(AsmError::NotZeroPage { address: 0x0100, .. })
+    ));
+    assert!(matches!(
+        assemble("#01"),
+        Err(AsmError::ZeroPageWrite { .. })
+    ));
+}
+
+#[test]
+fn strip_nested_comments() {
+    let src = "( header\n  ( nested ) still comment )\n|0100 ( -> ) #01 #18 DEO ( print )\nBRK";
+    let stripped = strip_comments(src);
+    assert_eq!(
+        stripped,
+        "        \n                            \n|0100        #01 #18 DEO          \nBRK"
+    );
+    assert_eq!(stripped.len(), src.len());
+    assert_eq!(stripped.lines().nth(2).unwrap().find("#01"), src.lines().nth(2).unwrap().find("#01"));
+    assert_eq!(assemble(&stripped), assemble(src));
+}
+
+#[test]
+fn assemble_from_reader() {
+    let src = "( multi-line
+  ( nested ) comment )
+|0100 @main
+    LIT2
+    1234 ;main/end JMP2 ( skip )
+    &end BRK
+";
+    let assembled = assemble_reader(std::io::Cursor::new(src)).unwrap();
+    assert_eq!(assembled, assemble(src).unwrap());
+    assert_eq!(assembled.symbols[1], (0x0107, "main/end".to_string()));
+
+    let src = "|0100\n( comment\n)\n  ;nope\n";
+    let err = assemble_reader(std::io::Cursor::new(src)).unwrap_err();
+    assert_eq!(err, assemble(src).unwrap_err());
+    assert_eq!((err.span().line, err.span().column), (4, 3));
+
+    let src = "|0100 ( unterminated\n#01\n";
+    assert_eq!(
+        assemble_reader(std::io::Cursor::new(src)),
+        assemble(src)
+    );
+}
+
+#[test]
+fn assemble_relative_references() {
+    // LIT fwd JMP #01 @fwd
+    assert_eq!(
+        assemble("|0100 ,fwd JMP #01 @fwd BRK").unwrap().rom,
+        vec![0x80, 0x02, 0x0c, 0x80, 0x01, 0x00]
+    );
+    assert_eq!(
+        assemble("|0100 @back #01 ,back JCN BRK").unwrap().rom,
+        vec![0x80, 0x01, 0x80, 0xfb, 0x0d, 0x00]
+    );
+    assert_eq!(
+        assemble("|0100 @loop ,&end JMP $7f &end BRK").unwrap().rom[..2],
+        [0x80, 0x7f]
+    );
+
+    assert!(matches!(
+        assemble("|0100 ,far JMP $80 @far"),
+        Err(AsmError::RelativeOutOfRange { offset: 128, .. })
+    ));
+    assert!(matches!(
+        assemble("|0100 @near $7e ,near JMP"),
+        Err(AsmError::RelativeOutOfRange { offset: -129, .. })
+    ));
+}
+
+#[test]
+fn assemble_rejects_illegal_modes() {
+    assert_eq!(assemble("|0100 BRK").unwrap().rom, vec![0x00]);
+    assert_eq!(assemble("|0100 #01").unwrap().rom, vec![0x80, 0x01]);
+    assert_eq!(assemble("|0100 LIT2kr 1234 ADD2kr").unwrap().rom, vec![0xe0, 0x12, 0x34, 0xf8]);
+
+    for src in ["BRKk", "BRK2", "BRKr", "BRK2kr"] {
+        match assemble(&format!("|0100 {}", src)) {
+            Err(AsmError::IllegalMode {
+                at, instruction, ..
+            }) => {
+                assert_eq!(instruction, src);
+                assert_eq!((at.column, at.len), (7, src.len()));
+            }
+            other => panic!("{}: {:?}", src, other),
+        }
+    }
+}
+
+#[test]
+fn assemble_ignores_brackets() {
+    assert_eq!(
+        assemble("|0100 [ #01 #02 ADD ] [ ( comment ) ]").unwrap(),
+        assemble("|0100 #01 #02 ADD").unwrap()
+    );
+    assert!(matches!(
+        assemble("|0100 [#01 ]"),
+        Err(AsmError::Syntax { .. })
+    ));
+}
+
+#[test]
+fn assemble_project_shares_labels() {
+    let fixture = |name: &str| {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/project").join(name)
+    };
+    let assembled = assemble_project(&[fixture("data.tal"), fixture("main.tal")]).unwrap();
+    assert_eq!(&assembled.rom[..4], &[0xa0, 0x02, 0x00, 0x00]);
+    assert_eq!(&assembled.rom[0x100..], b"hi\0");
+
+    assert!(matches!(
+        assemble_project(&[fixture("data.tal"), fixture("duplicate.tal")]),
+        Err(AsmError::DuplicateLabel { name, .. }) if name == "message"
+    ));
+    assert!(matches!(
+        assemble_project(&[fixture("missing.tal")]),
+        Err(AsmError::Io { .. })
+    ));
+}
+
+#[test]
+fn stack_depth_warnings() {
+    assert_eq!(
+        check_stack_depth("|0100 #01 ADD BRK").unwrap(),
+        vec![AsmWarning::PossibleUnderflow {
+            at: Span {
+                line: 1,
+                column: 11,
+                len: 3
+            },
+            depth: -1
+        }]
+    );
+    // a conditional jump falls through with what is left
+    let warnings = check_stack_depth("|0100 #01 #00 ,&end JCN POP POP &end BRK").unwrap();
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].span().column, 29);
+
+    // routines take arguments and return results
+    assert_eq!(
+        check_stack_depth(
+            "|0100 #41 ;print JSR2 ;two JSR2 ADD ;print JSR2 BRK
+            @print #18 DEO JMP2r
+            @two #01 #01 JMP2r
+            @on-console .Console/read DEI ?{ POP } BRK"
+        )
+        .unwrap(),
+        vec![]
+    );
+}
+
+#[test]
+fn assemble_macros() {
+    assert_eq!(
+        assemble("%TWO { #02 } %FOUR { TWO TWO ADD } |0100 FOUR BRK").unwrap(),
+        assemble("|0100 #02 #02 ADD BRK").unwrap()
+    );
+    // only expanding a recursive macro is an error
+    assert!(assemble("%LOOP { LOOP } |0100 BRK").is_ok());
+    assert_eq!(
+        assemble("%LOOP { LOOP } |0100 LOOP"),
+        Err(AsmError::RecursiveMacro {
+            at: Span {
+                line: 1,
+                column: 22,
+                len: 4
+            },
+            name: "LOOP".to_string()
+        })
+    );
+    assert!(matches!(
+        assemble("%PING { #01 PONG } %PONG { PING } |0100 PING"),
+        Err(AsmError::RecursiveMacro { name, .. }) if name == "PING"
+    ));
+    assert!(matches!(
+        assemble("%OPEN { #01 "),
+        Err(AsmError::UnclosedMacro { name, .. }) if name == "OPEN"
+    ));
+
+    let src = "%EMIT { ( write to the console )\n  #18 DEO\n}\n|0100 #41 EMIT\n";
+    assert_eq!(
+        assemble_reader(src.as_bytes()).unwrap(),
+        assemble("|0100 #41 #18 DEO").unwrap()
+    );
+
+    // the expansion before the comment is lexed again with the next line, and must keep the
+    // names of its labels
+    let src = "%COUNTDOWN { &loop #01 SUB DUP ,&loop JCN POP }
+        |0100 @main #03 COUNTDOWN ( from three,
+        then from five ) #05 COUNTDOWN BRK";
+    assert_eq!(
+        assemble_reader(src.as_bytes()).unwrap(),
+        assemble(src).unwrap()
+    );
+}
+
+#[test]
+fn macro_local_labels() {
+    let src = "%COUNTDOWN { &loop #01 SUB DUP ,&loop JCN POP }
+        |0100 @main #03 COUNTDOWN #05 COUNTDOWN BRK";
+    let assembled = assemble(src).unwrap();
+    let address = |name: &str| {
+        assembled
+            .symbols
+            .iter()
+            .find(|(_, label)| label == name)
+            .map(|(address, _)| *address)
+    };
+    assert_eq!(address("main/loop~1"), Some(0x102));
+    assert_eq!(address("main/loop~2"), Some(0x10c));
+    // each expansion jumps back to its own loop
+    assert_eq!(&assembled.rom[0x06..0x08], &[0x80, 0xf9]);
+    assert_eq!(&assembled.rom[0x10..0x12], &[0x80, 0xf9]);
+
+    let src = "%ROUTINE { ;routine/end JMP2 @routine &end }
+        |0100 ROUTINE ROUTINE BRK";
+    let assembled = assemble(src).unwrap();
+    assert_eq!(&assembled.rom[..3], &[0xa0, 0x01, 0x04]);
+    assert_eq!(&assembled.rom[4..7], &[0xa0, 0x01, 0x08]);
+}
+
+#[test]
+fn assemble_listing() {
+    let src = "|0100 @main ( print a character )
+        #41 #18 DEO
+        ;main JMP2
+        BRK \"hi 0a";
+    assert_eq!(
+        assemble(src).unwrap().listing(),
+        "0100: 80 41 80 18 17           ; #41 #18 DEO
+0105: a0 01 00 2c              ; ;main JMP2
+0109: 00 68 69 0a              ; BRK 68 69 0a
+"
+    );
+}
+
+#[test]
+fn assemble_fragments_into_one_rom() {
+    let mut rom = vec![];
+    let first = assemble_into("@main #0105 JSR2 BRK", &mut rom).unwrap();
+    assert_eq!(first.origin, 0x100);
+    assert_eq!(rom, vec![0xa0, 0x01, 0x05, 0x2e, 0x00]);
+
+    let second = assemble_into("@helper #01 #02 ADD JMP2r", &mut rom).unwrap();
+    assert_eq!(second.origin, 0x105);
+    assert_eq!(second.symbols, vec![(0x105, "helper".to_string())]);
+    assert_eq!(
+        rom,
+        vec![0xa0, 0x01, 0x05, 0x2e, 0x00, 0x80, 0x01, 0x80, 0x02, 0x18, 0x6c]
+    );
+}
+
+#[test]
+fn suggest_mnemonics() {
+    let suggestion = |src: &str| match assemble(&format!("|0100 {}", src)) {
+        Err(AsmError::UnknownOpcode {
+            got, suggestion, ..
+        }) => (got, suggestion),
+        other => panic!("{:?}", other),
+    };
+    assert_eq!(suggestion("DUB"), ("DUB".to_string(), "DUP".to_string()));
+    assert_eq!(suggestion("ADDD"), ("ADDD".to_string(), "ADD".to_string()));
+    assert_eq!(suggestion("SUBB2k"), ("SUBB".to_string(), "SUB".to_string()));
+    assert_eq!(suggestion("BKR"), ("BKR".to_string(), "BRK".to_string()));
+    assert_eq!(
+        assemble("|0100 #01 DUB").unwrap_err().to_string(),
+        "unknown instruction `DUB`, did you mean `DUP`?"
+    );
+}
+
+#[test]
+fn tokenize_source() {
+    let at = |column, len| Span {
+        line: 1,
+        column,
+        len,
+    };
+    let tokens = tokenize("@main ( init ) [ #01 ;data ] %inc { INC } inc").unwrap();
+    assert_eq!(
+        tokens,
+        vec![
+            (
+                at(1, 5),
+                SourceToken::Token(Token::Label {
+                    name: "main".to_string(),
+                    type_: LabelType::Parent
+                })
+            ),
+            (at(7, 8), SourceToken::Comment("( init )".to_string())),
+            (at(16, 1), SourceToken::Bracket('[')),
+            (
+                at(18, 3),
+                SourceToken::Token(Token::Instruction {
+                    opcode: Opcode::LIT,
+                    mode: InstructionMode::Keep,
+                    immediate: 0x01
+                })
+            ),
+            (
+                at(22, 5),
+                SourceToken::Token(Token::Reference {
+                    mode: AddressingMode::LiteralAbsolute,
+                    label: "data".to_string()
+                })
+            ),
+            (at(28, 1), SourceToken::Bracket(']')),
+            (at(30, 4), SourceToken::MacroDefinition("inc".to_string())),
+            (at(35, 1), SourceToken::Bracket('{')),
+            (
+                at(37, 3),
+                SourceToken::Token(Token::Instruction {
+                    opcode: Opcode::INC,
+                    mode: InstructionMode::None,
+                    immediate: 0
+                })
+            ),
+            (at(41, 1), SourceToken::Bracket('}')),
+            (at(43, 3), SourceToken::MacroCall("inc".to_string())),
+        ]
+    );
+    assert!(matches!(
+        tokenize("#01\n DUB"),
+        Err(AsmError::UnknownOpcode { at: Span { line: 2, column: 2, len: 3 }, .. })
+    ));
+}
+
+#[test]
+fn assemble_addressing_runes() {
+    // @zero is in the zero page, @here at 0x0100, @there at 0x0108
+    let rom = |refs: &str| {
+        assemble(&format!("|0012 @zero |0100 @here {} |0108 @there", refs))
+            .unwrap()
+            .rom
+    };
+    assert_eq!(rom(".zero"), vec![0x80, 0x12]);
+    // relative to the address after the LIT and the instruction following it
+    assert_eq!(rom(",there"), vec![0x80, 0x05]);
+    assert_eq!(rom(",here"), vec![0x80, 0xfd]);
+    assert_eq!(rom(";there"), vec![0xa0, 0x01, 0x08]);
+    assert_eq!(rom(":there"), vec![0x01, 0x08]);
+
+    // the runes also take a literal address
+    assert_eq!(
+        assemble("|0100 .12 ,7f ;1234 :1234").unwrap().rom,
+        vec![0x80, 0x12, 0x80, 0x7f, 0xa0, 0x12, 0x34, 0x12, 0x34]
+    );
+}
+
+#[test]
+fn assemble_skip_blocks() {
+    // the jump is relative to the address after its operand
+    assert_eq!(
+        assemble("|0100 #01 ?{ #ff } BRK").unwrap().rom,
+        vec![0x80, 0x01, 0x20, 0x00, 0x02, 0x80, 0xff, 0x00]
+    );
+    assert_eq!(
+        assemble("|0100 !{ ?{ INC } } BRK").unwrap().rom,
+        vec![0x40, 0x00, 0x04, 0x20, 0x00, 0x01, 0x01, 0x00]
+    );
+    // blocks don't change the parent of sublabels
+    let assembled = assemble("|0100 @main !{ &a } &b BRK").unwrap();
+    assert!(assembled.symbols.contains(&(0x0103, "main/b".to_string())));
+
+    assert!(matches!(
+        assemble("|0100 ?{ BRK"),
+        Err(AsmError::UnclosedBlock { .. })
+    ));
+    assert!(matches!(
+        assemble("|0100 BRK }"),
+        Err(AsmError::Syntax { .. })
+    ));
+}
+
+#[test]
+fn assemble_jump_table() {
+    // the table comes before the handlers, its addresses are resolved in the second pass
+    let assembled = assemble(
+        "|0100 @table =handler0 =handler1 :handler2
+        @handler0 BRK @handler1 BRK @handler2 BRK",
+    )
+    .unwrap();
+    assert_eq!(assembled.rom[..6], [0x01, 0x06, 0x01, 0x07, 0x01, 0x08]);
+    assert_eq!(assemble("|0100 =0123").unwrap().rom, vec![0x01, 0x23]);
+}
+
+#[test]
+fn assemble_device_header() {
+    // the zero page and device paddings lay out labels, but the ROM starts at 0x0100
+    let assembled = assemble(
+        "|00 @System &vector $2 &wst $1 &rst $1
+        |10 @Console &vector $2 &read $1 &pad $5 &write $1
+        |0000 @counter $2
+        |0100 #41 .Console/write DEO BRK",
+    )
+    .unwrap();
+    assert_eq!(assembled.rom, vec![0x80, 0x41, 0x80, 0x18, 0x17, 0x00]);
+    assert!(assembled.symbols.contains(&(0x0018, "Console/write".to_string())));
+    assert!(assembled.symbols.contains(&(0x0000, "counter".to_string())));
+}
+
+#[test]
+fn assemble_body_padding() {
+    let assembled = assemble("|0100 #01 @buf $40 @after BRK").unwrap();
+    assert!(assembled.symbols.contains(&(0x0102, "buf".to_string())));
+    assert!(assembled.symbols.contains(&(0x0142, "after".to_string())));
+    assert_eq!(assembled.rom.len(), 0x43);
+    assert!(assembled.rom[0x02..0x42].iter().all(|b| *b == 0));
+
+    // a buffer at the end of the program is part of the ROM too
+    assert_eq!(assemble("|0100 #01 @buf $40").unwrap().rom.len(), 0x42);
+}
+
+#[test]
+fn assemble_strict_data() {
+    // a lowercase `ADD2` is the raw short 0xadd2
+    let src = "|0100 #01 #02 add2 BRK";
+    assert_eq!(
+        assemble(src).unwrap().rom,
+        vec![0x80, 0x01, 0x80, 0x02, 0xad, 0xd2, 0x00]
+    );
+    assert!(matches!(
+        assemble_strict(src),
+        Err(AsmError::AmbiguousData {
+            at: Span { line: 1, column: 15, len: 4 },
+            ..
+        })
+    ));
+
+    // labelled tables, strings and jump tables are data
+    let src = "|0100 BRK @table 01 02 0304 &name \"hi 00 @jumps =table 05";
+    assert_eq!(assemble_strict(src).unwrap().rom, assemble(src).unwrap().rom);
+}
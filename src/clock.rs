@@ -0,0 +1,78 @@
+//! Time as seen by the VM and its devices, so that frontends and tests control it.
+
+use crate::devices::audio::SAMPLE_RATE;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+/// A source of time, given to [`crate::uxn::Uxn::with_clock`] and to the devices that need it.
+pub trait Clock: Send + Sync {
+    /// The current wall clock time.
+    fn now(&self) -> SystemTime;
+
+    /// Audio samples elapsed since the clock was created.
+    fn elapsed_samples(&self) -> usize;
+}
+
+fn samples(elapsed: Duration) -> usize {
+    (elapsed.as_micros() * SAMPLE_RATE as u128 / 1_000_000) as usize
+}
+
+/// The system's time.
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        SystemClock {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn elapsed_samples(&self) -> usize {
+        samples(self.start.elapsed())
+    }
+}
+
+/// A clock that only moves when advanced, for tests.
+pub struct ManualClock {
+    // the current time, and the time elapsed since creation
+    state: Mutex<(SystemTime, Duration)>,
+}
+
+impl ManualClock {
+    /// A clock stopped at `now`.
+    pub fn new(now: SystemTime) -> Self {
+        ManualClock {
+            state: Mutex::new((now, Duration::ZERO)),
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.0 += by;
+        state.1 += by;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        self.state.lock().unwrap().0
+    }
+
+    fn elapsed_samples(&self) -> usize {
+        samples(self.state.lock().unwrap().1)
+    }
+}
@@ -0,0 +1,93 @@
+use crate::uxn::{Device, ExecutionResult, PortAddress, UxnError};
+
+// https://wiki.xxiivv.com/site/varvara.html#audio
+const PORT_POSITION: PortAddress = 0x02;
+const PORT_ADSR: PortAddress = 0x08;
+const PORT_LENGTH: PortAddress = 0x0a;
+const PORT_PITCH: PortAddress = 0x0f;
+
+pub const SAMPLE_RATE: usize = 44100;
+
+/// An audio channel, usually installed in slots 3 to 6. Only keeps track of how long the
+/// current note plays, producing the samples is up to the frontend.
+#[derive(Default)]
+pub struct AudioDevice {
+    adsr: u16,
+    length: u16,
+    position: u16,
+    remaining: usize,
+}
+
+impl AudioDevice {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.remaining > 0
+    }
+
+    /// Plays the current note for up to `samples` samples, returning how many were played.
+    pub fn advance(&mut self, samples: usize) -> usize {
+        let played = samples.min(self.remaining);
+        self.remaining -= played;
+        self.position = self.position.wrapping_add(played as u16);
+        played
+    }
+
+    fn play(&mut self, pitch: u8) {
+        self.position = 0;
+        self.remaining = if pitch == 0 {
+            0
+        } else if self.adsr == 0 {
+            // without an envelope the sample plays once
+            self.length as usize
+        } else {
+            // each envelope nibble counts in fifteenths of a second
+            let steps: usize = (0..4).map(|i| (self.adsr >> (i * 4) & 0xf) as usize).sum();
+            steps * SAMPLE_RATE / 15
+        };
+    }
+}
+
+impl Device for AudioDevice {
+    fn dei(&self, port: PortAddress) -> ExecutionResult<u8> {
+        match port {
+            PORT_POSITION => Ok((self.position >> 8) as u8),
+            0x03 => Ok(self.position as u8),
+            _ => Err(UxnError::Device {
+                name: self.name(),
+                reason: "unhandled port",
+            }),
+        }
+    }
+
+    fn deo(&mut self, port: PortAddress, value: u8) -> ExecutionResult<()> {
+        match port {
+            PORT_ADSR => self.adsr = (self.adsr & 0x00ff) | (value as u16) << 8,
+            0x09 => self.adsr = (self.adsr & 0xff00) | value as u16,
+            PORT_LENGTH => self.length = (self.length & 0x00ff) | (value as u16) << 8,
+            0x0b => self.length = (self.length & 0xff00) | value as u16,
+            PORT_PITCH => self.play(value),
+            _ => {
+                return Err(UxnError::Device {
+                    name: self.name(),
+                    reason: "unhandled port",
+                })
+            }
+        }
+        Ok(())
+    }
+
+    fn dei_mask(&self) -> u16 {
+        0x000c
+    }
+
+    fn deo_mask(&self) -> u16 {
+        0x8f00
+    }
+
+    fn name(&self) -> &'static str {
+        "audio"
+    }
+}
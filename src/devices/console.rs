@@ -0,0 +1,217 @@
+use crate::uxn::{Device, ExecutionResult, PortAddress, UxnError};
+use std::io::Write;
+
+// https://wiki.xxiivv.com/site/varvara.html#console
+pub(crate) const PORT_READ: PortAddress = 0x02;
+pub(crate) const PORT_TYPE: PortAddress = 0x07;
+const PORT_WRITE: PortAddress = 0x08;
+const PORT_ERROR: PortAddress = 0x09;
+
+/// What a byte given to [`crate::uxn::Uxn::feed_console`] is, as read from the type port.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ConsoleInputKind {
+    /// A byte read from standard input.
+    Stdin = 1,
+    /// A byte of a command-line argument.
+    Argument = 2,
+    /// The end of an argument, another one follows.
+    ArgumentSeparator = 3,
+    /// The end of the last argument.
+    Eof = 4,
+}
+
+enum Output {
+    Stdio,
+    Capture { out: Vec<u8>, err: Vec<u8> },
+}
+
+/// The console device, usually installed in slot 1. Bytes written to the write and error ports
+/// go to stdout and stderr, or to in-memory buffers when created with
+/// [`ConsoleDevice::with_capture`].
+pub struct ConsoleDevice {
+    output: Output,
+    /// The unfinished line of the write port, when line buffered.
+    line: Option<Vec<u8>>,
+}
+
+impl ConsoleDevice {
+    pub fn new() -> Self {
+        ConsoleDevice {
+            output: Output::Stdio,
+            line: None,
+        }
+    }
+
+    /// A console keeping its output in memory instead of writing it, for headless tests.
+    pub fn with_capture() -> Self {
+        ConsoleDevice {
+            output: Output::Capture {
+                out: vec![],
+                err: vec![],
+            },
+            line: None,
+        }
+    }
+
+    /// Holds bytes written to the write port until a newline, or until the VM halts.
+    pub fn line_buffered(mut self) -> Self {
+        self.line = Some(vec![]);
+        self
+    }
+
+    fn write_out(&mut self, bytes: &[u8]) -> ExecutionResult<()> {
+        match &mut self.output {
+            Output::Capture { out, .. } => out.extend_from_slice(bytes),
+            Output::Stdio => std::io::stdout()
+                .write_all(bytes)
+                .or(Err(UxnError::Device {
+                    name: self.name(),
+                    reason: "cannot write to stdout",
+                }))?,
+        }
+        Ok(())
+    }
+
+    /// Bytes written to the write port so far. Always empty unless capturing.
+    pub fn captured(&self) -> &[u8] {
+        match &self.output {
+            Output::Capture { out, .. } => out,
+            Output::Stdio => &[],
+        }
+    }
+
+    /// Bytes written to the error port so far. Always empty unless capturing.
+    pub fn captured_error(&self) -> &[u8] {
+        match &self.output {
+            Output::Capture { err, .. } => err,
+            Output::Stdio => &[],
+        }
+    }
+}
+
+impl Default for ConsoleDevice {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Device for ConsoleDevice {
+    fn dei(&self, _port: PortAddress) -> ExecutionResult<u8> {
+        Err(UxnError::Device {
+            name: self.name(),
+            reason: "unhandled port",
+        })
+    }
+
+    fn deo(&mut self, port: PortAddress, value: u8) -> ExecutionResult<()> {
+        match (&mut self.output, port) {
+            (_, PORT_WRITE) => match &mut self.line {
+                Some(line) if value != b'\n' => line.push(value),
+                Some(line) => {
+                    let mut line = std::mem::take(line);
+                    line.push(value);
+                    self.write_out(&line)?;
+                }
+                None => self.write_out(&[value])?,
+            },
+            (Output::Capture { err, .. }, PORT_ERROR) => err.push(value),
+            (Output::Stdio, PORT_ERROR) => {
+                std::io::stderr()
+                    .write_all(&[value])
+                    .or(Err(UxnError::Device {
+                        name: self.name(),
+                        reason: "cannot write to stderr",
+                    }))?
+            }
+            _ => {
+                return Err(UxnError::Device {
+                    name: self.name(),
+                    reason: "unhandled port",
+                })
+            }
+        }
+        Ok(())
+    }
+
+    fn dei_mask(&self) -> u16 {
+        0x0000
+    }
+
+    fn deo_mask(&self) -> u16 {
+        1 << PORT_WRITE | 1 << PORT_ERROR
+    }
+
+    fn name(&self) -> &'static str {
+        "console"
+    }
+
+    fn on_halt(&mut self) -> ExecutionResult<()> {
+        if let Some(line) = self.line.as_mut().map(std::mem::take) {
+            self.write_out(&line)?;
+        }
+        if let Output::Stdio = self.output {
+            std::io::stdout().flush().or(Err(UxnError::Device {
+                name: self.name(),
+                reason: "cannot flush stdout",
+            }))?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn capture_console_output() {
+    use crate::uxn::Uxn;
+
+    let mut uxn = Uxn::new();
+    uxn.boot();
+    uxn.set_device(1, Box::new(ConsoleDevice::with_capture()));
+    let rom = crate::assembler::assemble(
+        "|0100 @main ;text
+        &loop
+            DUP2 LDA #18 DEO
+            INC2 DUP2 LDA ;&loop JCN2
+        POP2
+        #21 #19 DEO
+        BRK
+        @text \"Hello, 20 \"World 0a 00",
+    )
+    .unwrap();
+    uxn.load_program(&rom.rom, 0x100);
+    uxn.eval(0x100).unwrap();
+
+    let console = uxn.device(1).downcast_ref::<ConsoleDevice>().unwrap();
+    assert_eq!(console.captured(), b"Hello, World\n");
+    assert_eq!(console.captured_error(), b"!");
+}
+
+#[test]
+fn line_buffered_console_flushes_on_halt() {
+    use crate::uxn::Uxn;
+
+    let mut uxn = Uxn::try_from(
+        "|0100 #6f #18 DEO #6b #18 DEO #0a #18 DEO #68 #18 DEO BRK
+        @halt #69 #18 DEO #01 #0f DEO BRK",
+    )
+    .unwrap();
+    let console = ConsoleDevice::with_capture().line_buffered();
+    uxn.set_device(1, Box::new(console));
+    let captured = |uxn: &Uxn| {
+        let console = uxn.device(1).downcast_ref::<ConsoleDevice>().unwrap();
+        console.captured().to_vec()
+    };
+
+    uxn.eval(0x100).unwrap();
+    assert_eq!(captured(&uxn), b"ok\n");
+    uxn.eval(0x115).unwrap();
+    assert!(uxn.is_halted());
+    assert_eq!(captured(&uxn), b"ok\nhi");
+
+    // the host halting the VM flushes the line too
+    uxn = Uxn::try_from("|0100 #6f #18 DEO BRK").unwrap();
+    uxn.set_device(1, Box::new(ConsoleDevice::with_capture().line_buffered()));
+    uxn.eval(0x100).unwrap();
+    assert_eq!(captured(&uxn), b"");
+    uxn.halt().unwrap();
+    assert_eq!(captured(&uxn), b"o");
+}
@@ -0,0 +1,168 @@
+use crate::clock::Clock;
+use crate::uxn::{Device, ExecutionResult, PortAddress, UxnError};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+// https://wiki.xxiivv.com/site/varvara.html#datetime
+const PORT_YEAR: PortAddress = 0x00;
+const PORT_MONTH: PortAddress = 0x02;
+const PORT_DAY: PortAddress = 0x03;
+const PORT_HOUR: PortAddress = 0x04;
+const PORT_MINUTE: PortAddress = 0x05;
+const PORT_SECOND: PortAddress = 0x06;
+const PORT_DOTW: PortAddress = 0x07;
+const PORT_DOTY: PortAddress = 0x08;
+const PORT_ISDST: PortAddress = 0x0a;
+
+/// The date and time in UTC, as read from the device's ports.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+struct DateTime {
+    year: u16,
+    /// 0 to 11.
+    month: u8,
+    /// 1 to 31.
+    day: u8,
+    hour: u8,
+    minute: u8,
+    second: u8,
+    /// Day of the week, 0 being sunday.
+    dotw: u8,
+    /// Day of the year, starting at 0.
+    doty: u16,
+}
+
+fn is_leap(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+impl DateTime {
+    fn from_unix(seconds: i64) -> Self {
+        let days = seconds.div_euclid(86400);
+        let time = seconds.rem_euclid(86400);
+
+        // civil date from days since 1970-01-01, see
+        // https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z.rem_euclid(146097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 2 } else { mp - 10 };
+        let year = yoe + era * 400 + if month < 2 { 1 } else { 0 };
+
+        const DAYS_BEFORE_MONTH: [i64; 12] =
+            [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334];
+        let leap_day = if month >= 2 && is_leap(year) { 1 } else { 0 };
+        let doty = DAYS_BEFORE_MONTH[month as usize] + leap_day + day - 1;
+
+        DateTime {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (time / 3600) as u8,
+            minute: (time / 60 % 60) as u8,
+            second: (time % 60) as u8,
+            // 1970-01-01 was a thursday
+            dotw: (days + 4).rem_euclid(7) as u8,
+            doty: doty as u16,
+        }
+    }
+}
+
+/// The datetime device, usually installed in slot 0xc. Reads the time from a [`Clock`], in UTC.
+pub struct DateTimeDevice {
+    clock: Arc<dyn Clock>,
+}
+
+impl DateTimeDevice {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        DateTimeDevice { clock }
+    }
+
+    fn now(&self) -> DateTime {
+        let seconds = match self.clock.now().duration_since(UNIX_EPOCH) {
+            Ok(since) => since.as_secs() as i64,
+            Err(before) => -(before.duration().as_secs() as i64),
+        };
+        DateTime::from_unix(seconds)
+    }
+}
+
+impl Device for DateTimeDevice {
+    fn dei(&self, port: PortAddress) -> ExecutionResult<u8> {
+        let now = self.now();
+        match port {
+            PORT_YEAR => Ok((now.year >> 8) as u8),
+            0x01 => Ok(now.year as u8),
+            PORT_MONTH => Ok(now.month),
+            PORT_DAY => Ok(now.day),
+            PORT_HOUR => Ok(now.hour),
+            PORT_MINUTE => Ok(now.minute),
+            PORT_SECOND => Ok(now.second),
+            PORT_DOTW => Ok(now.dotw),
+            PORT_DOTY => Ok((now.doty >> 8) as u8),
+            0x09 => Ok(now.doty as u8),
+            PORT_ISDST => Ok(0),
+            _ => Err(UxnError::Device {
+                name: self.name(),
+                reason: "unhandled port",
+            }),
+        }
+    }
+
+    fn deo(&mut self, _port: PortAddress, _value: u8) -> ExecutionResult<()> {
+        Err(UxnError::Device {
+            name: self.name(),
+            reason: "unhandled port",
+        })
+    }
+
+    fn dei_mask(&self) -> u16 {
+        0x07ff
+    }
+
+    fn deo_mask(&self) -> u16 {
+        0x0000
+    }
+
+    fn name(&self) -> &'static str {
+        "datetime"
+    }
+}
+
+#[test]
+fn dates_from_unix_time() {
+    assert_eq!(
+        DateTime::from_unix(0),
+        DateTime {
+            year: 1970,
+            month: 0,
+            day: 1,
+            hour: 0,
+            minute: 0,
+            second: 0,
+            dotw: 4,
+            doty: 0,
+        }
+    );
+    // 2024-02-29 23:59:58, a thursday
+    assert_eq!(
+        DateTime::from_unix(1709251198),
+        DateTime {
+            year: 2024,
+            month: 1,
+            day: 29,
+            hour: 23,
+            minute: 59,
+            second: 58,
+            dotw: 4,
+            doty: 59,
+        }
+    );
+    // 2023-12-31, a sunday
+    let date = DateTime::from_unix(1703980800);
+    assert_eq!((date.year, date.month, date.day), (2023, 11, 31));
+    assert_eq!((date.dotw, date.doty), (0, 364));
+}
@@ -0,0 +1,193 @@
+use super::screen::{set_high, set_low, Framebuffer};
+use crate::uxn::{Device, ExecutionResult, PortAddress, UxnError};
+use std::sync::{Arc, Mutex, PoisonError};
+
+// not a varvara device, the ports follow the screen's
+const PORT_X: PortAddress = 0x08;
+const PORT_Y: PortAddress = 0x0a;
+const PORT_COLOR: PortAddress = 0x0c;
+const PORT_CHAR: PortAddress = 0x0d;
+
+/// A device printing ASCII onto a screen with a built-in 5x7 font, to debug a ROM
+/// before it has a font of its own. Not part of varvara, install it in a free slot.
+///
+/// Writing a character to port 0x0d draws it at the position in ports 0x08 (x) and 0x0a (y)
+/// in the color in port 0x0c, and moves the position right by a tile. The color byte works
+/// like the screen's sprite port, e.g. 0x41 draws in color 1 on the foreground.
+pub struct DebugTextDevice {
+    screen: Arc<Mutex<Framebuffer>>,
+    x: u16,
+    y: u16,
+    color: u8,
+}
+
+impl DebugTextDevice {
+    /// A device drawing on `screen`, e.g. [`super::ScreenDevice::framebuffer`].
+    pub fn new(screen: Arc<Mutex<Framebuffer>>) -> Self {
+        DebugTextDevice {
+            screen,
+            x: 0,
+            y: 0,
+            color: 0,
+        }
+    }
+}
+
+/// The 1bpp 8x8 tile of `char`. Characters outside of printable ASCII are blank.
+pub fn glyph(char: u8) -> &'static [u8; 8] {
+    match char {
+        0x20..=0x7e => &FONT[(char - 0x20) as usize],
+        _ => &FONT[0],
+    }
+}
+
+impl Device for DebugTextDevice {
+    fn dei(&self, port: PortAddress) -> ExecutionResult<u8> {
+        match port {
+            PORT_X => Ok((self.x >> 8) as u8),
+            0x09 => Ok(self.x as u8),
+            _ => Err(UxnError::Device {
+                name: self.name(),
+                reason: "unhandled port",
+            }),
+        }
+    }
+
+    fn deo(&mut self, port: PortAddress, value: u8) -> ExecutionResult<()> {
+        match port {
+            PORT_X => set_high(&mut self.x, value),
+            0x09 => set_low(&mut self.x, value),
+            PORT_Y => set_high(&mut self.y, value),
+            0x0b => set_low(&mut self.y, value),
+            PORT_COLOR => self.color = value,
+            PORT_CHAR => {
+                self.screen
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .draw_tile(glyph(value), self.x, self.y, self.color);
+                self.x = self.x.wrapping_add(8);
+            }
+            _ => {
+                return Err(UxnError::Device {
+                    name: self.name(),
+                    reason: "unhandled port",
+                })
+            }
+        }
+        Ok(())
+    }
+
+    // x moves as characters are written
+    fn dei_mask(&self) -> u16 {
+        0x0300
+    }
+
+    fn deo_mask(&self) -> u16 {
+        0x3f00
+    }
+
+    fn name(&self) -> &'static str {
+        "debug-text"
+    }
+}
+
+/// Printable ASCII from 0x20, in columns 1 to 5 and rows 0 to 6 of each tile, descenders in
+/// row 7.
+#[rustfmt::skip]
+const FONT: [[u8; 8]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // space
+    [0x10, 0x10, 0x10, 0x10, 0x10, 0x00, 0x10, 0x00], // !
+    [0x28, 0x28, 0x28, 0x00, 0x00, 0x00, 0x00, 0x00], // "
+    [0x28, 0x28, 0x7c, 0x28, 0x7c, 0x28, 0x28, 0x00], // #
+    [0x10, 0x3c, 0x50, 0x38, 0x14, 0x78, 0x10, 0x00], // $
+    [0x60, 0x64, 0x08, 0x10, 0x20, 0x4c, 0x0c, 0x00], // %
+    [0x30, 0x48, 0x50, 0x20, 0x54, 0x48, 0x34, 0x00], // &
+    [0x10, 0x10, 0x20, 0x00, 0x00, 0x00, 0x00, 0x00], // "'"
+    [0x08, 0x10, 0x20, 0x20, 0x20, 0x10, 0x08, 0x00], // (
+    [0x20, 0x10, 0x08, 0x08, 0x08, 0x10, 0x20, 0x00], // )
+    [0x00, 0x10, 0x54, 0x38, 0x54, 0x10, 0x00, 0x00], // *
+    [0x00, 0x10, 0x10, 0x7c, 0x10, 0x10, 0x00, 0x00], // +
+    [0x00, 0x00, 0x00, 0x00, 0x30, 0x10, 0x20, 0x00], // ,
+    [0x00, 0x00, 0x00, 0x7c, 0x00, 0x00, 0x00, 0x00], // -
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x30, 0x30, 0x00], // .
+    [0x00, 0x04, 0x08, 0x10, 0x20, 0x40, 0x00, 0x00], // /
+    [0x38, 0x44, 0x4c, 0x54, 0x64, 0x44, 0x38, 0x00], // 0
+    [0x10, 0x30, 0x10, 0x10, 0x10, 0x10, 0x38, 0x00], // 1
+    [0x38, 0x44, 0x04, 0x08, 0x10, 0x20, 0x7c, 0x00], // 2
+    [0x7c, 0x08, 0x10, 0x08, 0x04, 0x44, 0x38, 0x00], // 3
+    [0x08, 0x18, 0x28, 0x48, 0x7c, 0x08, 0x08, 0x00], // 4
+    [0x7c, 0x40, 0x78, 0x04, 0x04, 0x44, 0x38, 0x00], // 5
+    [0x18, 0x20, 0x40, 0x78, 0x44, 0x44, 0x38, 0x00], // 6
+    [0x7c, 0x04, 0x08, 0x10, 0x20, 0x20, 0x20, 0x00], // 7
+    [0x38, 0x44, 0x44, 0x38, 0x44, 0x44, 0x38, 0x00], // 8
+    [0x38, 0x44, 0x44, 0x3c, 0x04, 0x08, 0x30, 0x00], // 9
+    [0x00, 0x30, 0x30, 0x00, 0x30, 0x30, 0x00, 0x00], // :
+    [0x00, 0x30, 0x30, 0x00, 0x30, 0x10, 0x20, 0x00], // ;
+    [0x08, 0x10, 0x20, 0x40, 0x20, 0x10, 0x08, 0x00], // <
+    [0x00, 0x00, 0x7c, 0x00, 0x7c, 0x00, 0x00, 0x00], // =
+    [0x20, 0x10, 0x08, 0x04, 0x08, 0x10, 0x20, 0x00], // >
+    [0x38, 0x44, 0x04, 0x08, 0x10, 0x00, 0x10, 0x00], // ?
+    [0x38, 0x44, 0x04, 0x34, 0x54, 0x54, 0x38, 0x00], // @
+    [0x38, 0x44, 0x44, 0x7c, 0x44, 0x44, 0x44, 0x00], // A
+    [0x78, 0x44, 0x44, 0x78, 0x44, 0x44, 0x78, 0x00], // B
+    [0x38, 0x44, 0x40, 0x40, 0x40, 0x44, 0x38, 0x00], // C
+    [0x70, 0x48, 0x44, 0x44, 0x44, 0x48, 0x70, 0x00], // D
+    [0x7c, 0x40, 0x40, 0x78, 0x40, 0x40, 0x7c, 0x00], // E
+    [0x7c, 0x40, 0x40, 0x78, 0x40, 0x40, 0x40, 0x00], // F
+    [0x38, 0x44, 0x40, 0x5c, 0x44, 0x44, 0x3c, 0x00], // G
+    [0x44, 0x44, 0x44, 0x7c, 0x44, 0x44, 0x44, 0x00], // H
+    [0x38, 0x10, 0x10, 0x10, 0x10, 0x10, 0x38, 0x00], // I
+    [0x1c, 0x08, 0x08, 0x08, 0x08, 0x48, 0x30, 0x00], // J
+    [0x44, 0x48, 0x50, 0x60, 0x50, 0x48, 0x44, 0x00], // K
+    [0x40, 0x40, 0x40, 0x40, 0x40, 0x40, 0x7c, 0x00], // L
+    [0x44, 0x6c, 0x54, 0x54, 0x44, 0x44, 0x44, 0x00], // M
+    [0x44, 0x44, 0x64, 0x54, 0x4c, 0x44, 0x44, 0x00], // N
+    [0x38, 0x44, 0x44, 0x44, 0x44, 0x44, 0x38, 0x00], // O
+    [0x78, 0x44, 0x44, 0x78, 0x40, 0x40, 0x40, 0x00], // P
+    [0x38, 0x44, 0x44, 0x44, 0x54, 0x48, 0x34, 0x00], // Q
+    [0x78, 0x44, 0x44, 0x78, 0x50, 0x48, 0x44, 0x00], // R
+    [0x3c, 0x40, 0x40, 0x38, 0x04, 0x04, 0x78, 0x00], // S
+    [0x7c, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x00], // T
+    [0x44, 0x44, 0x44, 0x44, 0x44, 0x44, 0x38, 0x00], // U
+    [0x44, 0x44, 0x44, 0x44, 0x44, 0x28, 0x10, 0x00], // V
+    [0x44, 0x44, 0x44, 0x54, 0x54, 0x54, 0x28, 0x00], // W
+    [0x44, 0x44, 0x28, 0x10, 0x28, 0x44, 0x44, 0x00], // X
+    [0x44, 0x44, 0x44, 0x28, 0x10, 0x10, 0x10, 0x00], // Y
+    [0x7c, 0x04, 0x08, 0x10, 0x20, 0x40, 0x7c, 0x00], // Z
+    [0x38, 0x20, 0x20, 0x20, 0x20, 0x20, 0x38, 0x00], // [
+    [0x00, 0x40, 0x20, 0x10, 0x08, 0x04, 0x00, 0x00], // '\\'
+    [0x38, 0x08, 0x08, 0x08, 0x08, 0x08, 0x38, 0x00], // ]
+    [0x10, 0x28, 0x44, 0x00, 0x00, 0x00, 0x00, 0x00], // ^
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x7c, 0x00], // _
+    [0x20, 0x10, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00], // `
+    [0x00, 0x00, 0x38, 0x04, 0x3c, 0x44, 0x3c, 0x00], // a
+    [0x40, 0x40, 0x58, 0x64, 0x44, 0x44, 0x78, 0x00], // b
+    [0x00, 0x00, 0x38, 0x40, 0x40, 0x44, 0x38, 0x00], // c
+    [0x04, 0x04, 0x34, 0x4c, 0x44, 0x44, 0x3c, 0x00], // d
+    [0x00, 0x00, 0x38, 0x44, 0x7c, 0x40, 0x38, 0x00], // e
+    [0x18, 0x24, 0x20, 0x70, 0x20, 0x20, 0x20, 0x00], // f
+    [0x00, 0x00, 0x3c, 0x44, 0x44, 0x3c, 0x04, 0x38], // g
+    [0x40, 0x40, 0x58, 0x64, 0x44, 0x44, 0x44, 0x00], // h
+    [0x10, 0x00, 0x30, 0x10, 0x10, 0x10, 0x38, 0x00], // i
+    [0x08, 0x00, 0x18, 0x08, 0x08, 0x08, 0x48, 0x30], // j
+    [0x40, 0x40, 0x48, 0x50, 0x60, 0x50, 0x48, 0x00], // k
+    [0x30, 0x10, 0x10, 0x10, 0x10, 0x10, 0x38, 0x00], // l
+    [0x00, 0x00, 0x68, 0x54, 0x54, 0x44, 0x44, 0x00], // m
+    [0x00, 0x00, 0x58, 0x64, 0x44, 0x44, 0x44, 0x00], // n
+    [0x00, 0x00, 0x38, 0x44, 0x44, 0x44, 0x38, 0x00], // o
+    [0x00, 0x00, 0x78, 0x44, 0x44, 0x78, 0x40, 0x40], // p
+    [0x00, 0x00, 0x3c, 0x44, 0x44, 0x3c, 0x04, 0x04], // q
+    [0x00, 0x00, 0x58, 0x64, 0x40, 0x40, 0x40, 0x00], // r
+    [0x00, 0x00, 0x38, 0x40, 0x38, 0x04, 0x78, 0x00], // s
+    [0x20, 0x20, 0x70, 0x20, 0x20, 0x24, 0x18, 0x00], // t
+    [0x00, 0x00, 0x44, 0x44, 0x44, 0x4c, 0x34, 0x00], // u
+    [0x00, 0x00, 0x44, 0x44, 0x44, 0x28, 0x10, 0x00], // v
+    [0x00, 0x00, 0x44, 0x44, 0x54, 0x54, 0x28, 0x00], // w
+    [0x00, 0x00, 0x44, 0x28, 0x10, 0x28, 0x44, 0x00], // x
+    [0x00, 0x00, 0x44, 0x44, 0x44, 0x3c, 0x04, 0x38], // y
+    [0x00, 0x00, 0x7c, 0x08, 0x10, 0x20, 0x7c, 0x00], // z
+    [0x08, 0x10, 0x10, 0x20, 0x10, 0x10, 0x08, 0x00], // {
+    [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x00], // |
+    [0x20, 0x10, 0x10, 0x08, 0x10, 0x10, 0x20, 0x00], // }
+    [0x00, 0x00, 0x20, 0x54, 0x08, 0x00, 0x00, 0x00], // ~
+];
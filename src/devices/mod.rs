@@ -0,0 +1,40 @@
+//! Varvara devices, to be installed in a [`crate::uxn::Uxn`] with `set_device`.
+
+pub mod audio;
+pub mod console;
+pub mod datetime;
+pub mod debug_text;
+pub mod screen;
+
+pub use audio::AudioDevice;
+pub use console::{ConsoleDevice, ConsoleInputKind};
+pub use datetime::DateTimeDevice;
+pub use debug_text::DebugTextDevice;
+pub use screen::{Framebuffer, PixelFormat, ScreenDevice};
+
+/// Slot of the console device.
+pub const CONSOLE: u8 = 0x1;
+/// Slot of the screen device.
+pub const SCREEN: u8 = 0x2;
+/// Slots of the four audio channels.
+pub const AUDIO: [u8; 4] = [0x3, 0x4, 0x5, 0x6];
+/// Slot of the controller device.
+pub const CONTROLLER: u8 = 0x8;
+/// Slot of the mouse device.
+pub const MOUSE: u8 = 0x9;
+/// Slot of the datetime device.
+pub const DATETIME: u8 = 0xc;
+
+/// The order in which device vectors firing in the same frame run: the system, then the input
+/// devices (console, controller, mouse), then the screen, then audio, then the other slots.
+pub const VECTOR_ORDER: [u8; 16] = [
+    0x0, CONSOLE, CONTROLLER, MOUSE, SCREEN, 0x3, 0x4, 0x5, 0x6, 0x7, 0xa, 0xb, 0xc, 0xd, 0xe, 0xf,
+];
+
+/// Position of `slot` in [`VECTOR_ORDER`].
+pub fn vector_priority(slot: u8) -> usize {
+    VECTOR_ORDER
+        .iter()
+        .position(|s| *s == slot & 0xf)
+        .unwrap_or(VECTOR_ORDER.len())
+}
@@ -0,0 +1,387 @@
+use crate::uxn::{Device, ExecutionResult, PortAddress, Ram, UxnError};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+// https://wiki.xxiivv.com/site/varvara.html#screen
+const PORT_WIDTH: PortAddress = 0x02;
+const PORT_HEIGHT: PortAddress = 0x04;
+const PORT_AUTO: PortAddress = 0x06;
+const PORT_X: PortAddress = 0x08;
+const PORT_Y: PortAddress = 0x0a;
+const PORT_ADDR: PortAddress = 0x0c;
+const PORT_PIXEL: PortAddress = 0x0e;
+const PORT_SPRITE: PortAddress = 0x0f;
+
+/// The widths and heights a ROM may set, like varvara, so that a stray write can't allocate
+/// gigabytes of framebuffer.
+const SIZES: std::ops::RangeInclusive<u16> = 8..=0x800;
+
+// the color a sprite pixel is drawn in, by pixel value and blending mode
+const BLENDING: [[u8; 16]; 4] = [
+    [0, 0, 0, 0, 1, 0, 1, 1, 2, 2, 0, 2, 3, 3, 3, 0],
+    [0, 1, 2, 3, 0, 1, 2, 3, 0, 1, 2, 3, 0, 1, 2, 3],
+    [1, 2, 3, 1, 1, 2, 3, 1, 1, 2, 3, 1, 1, 2, 3, 1],
+    [2, 3, 1, 2, 2, 3, 1, 2, 2, 3, 1, 2, 2, 3, 1, 2],
+];
+// whether a blending mode draws the sprite's 0 pixels
+const OPAQUE: [bool; 16] = [
+    false, true, true, true, true, false, true, true, true, true, false, true, true, true, true,
+    false,
+];
+
+/// How [`ScreenDevice::render_frame`] lays out pixels.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum PixelFormat {
+    /// Four bytes per pixel: alpha, red, green and blue.
+    Argb8888,
+    /// Four bytes per pixel: red, green, blue and alpha.
+    Rgba8888,
+    /// A byte per pixel, its palette index, for frontends that upload the palette themselves.
+    Indexed8,
+}
+
+/// The pixels of a [`ScreenDevice`]: palette indices (0-3) on a background and a foreground
+/// layer. Shared through [`ScreenDevice::framebuffer`] with devices that draw on the screen.
+pub struct Framebuffer {
+    width: u16,
+    height: u16,
+    background: Vec<u8>,
+    foreground: Vec<u8>,
+    dirty: bool,
+}
+
+impl Framebuffer {
+    fn new(width: u16, height: u16) -> Self {
+        let size = width as usize * height as usize;
+        Framebuffer {
+            width,
+            height,
+            background: vec![0; size],
+            foreground: vec![0; size],
+            dirty: false,
+        }
+    }
+
+    /// The palette index at `x`, `y`: the foreground if it is set, the background otherwise.
+    pub fn pixel(&self, x: u16, y: u16) -> u8 {
+        let i = y as usize * self.width as usize + x as usize;
+        match self.foreground[i] {
+            0 => self.background[i],
+            color => color,
+        }
+    }
+
+    /// Draws a 1bpp 8x8 `tile` at `x`, `y`, with `value` read like the sprite port's: the
+    /// layer, flip and blending flags. For drawing outside the VM's memory, e.g.
+    /// [`crate::devices::DebugTextDevice`] glyphs.
+    pub fn draw_tile(&mut self, tile: &[u8; 8], x: u16, y: u16, value: u8) {
+        self.blit(|i| tile[i as usize], x, y, value & 0x7f);
+    }
+
+    fn resize(&mut self, width: u16, height: u16) {
+        let resized = |layer: &[u8]| {
+            let mut pixels = vec![0; width as usize * height as usize];
+            let keep = width.min(self.width) as usize;
+            for y in 0..height.min(self.height) as usize {
+                let from = y * self.width as usize;
+                let to = y * width as usize;
+                pixels[to..to + keep].copy_from_slice(&layer[from..from + keep]);
+            }
+            pixels
+        };
+        self.background = resized(&self.background);
+        self.foreground = resized(&self.foreground);
+        self.width = width;
+        self.height = height;
+        self.dirty = true;
+    }
+
+    fn layer(&mut self, value: u8) -> &mut Vec<u8> {
+        if value & 0x40 != 0 {
+            &mut self.foreground
+        } else {
+            &mut self.background
+        }
+    }
+
+    fn write(&mut self, layer: u8, x: u16, y: u16, color: u8) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        let i = y as usize * self.width as usize + x as usize;
+        self.layer(layer)[i] = color;
+        self.dirty = true;
+    }
+
+    /// Fills from `x`, `y` to the right and bottom edges. With the flip flags, fills from the
+    /// left or top edge up to the position, excluding its column or row.
+    fn fill(&mut self, x: u16, y: u16, value: u8) {
+        let (x0, x1) = if value & 0x10 != 0 {
+            (0, x)
+        } else {
+            (x, self.width)
+        };
+        let (y0, y1) = if value & 0x20 != 0 {
+            (0, y)
+        } else {
+            (y, self.height)
+        };
+        for y in y0..y1.min(self.height) {
+            for x in x0..x1.min(self.width) {
+                self.write(value, x, y, value & 0x03);
+            }
+        }
+    }
+
+    /// Draws the 8x8 tile whose `i`th byte is `byte(i)`: 8 bytes in 1bpp, or 16 bytes (two
+    /// planes) in 2bpp.
+    fn blit(&mut self, byte: impl Fn(u16) -> u8, x: u16, y: u16, value: u8) {
+        let two_bpp = value & 0x80 != 0;
+        let blend = (value & 0x0f) as usize;
+        let flip_x = value & 0x10 != 0;
+        let flip_y = value & 0x20 != 0;
+        let byte = |i: u16| byte(i) as u16;
+        for v in 0..8 {
+            let low = byte(v);
+            let high = if two_bpp { byte(v + 8) } else { 0 };
+            for h in 0..8 {
+                let shift = 7 - h;
+                let pixel = ((low >> shift) & 1 | ((high >> shift) & 1) << 1) as usize;
+                if pixel == 0 && !OPAQUE[blend] {
+                    continue;
+                }
+                let dx = if flip_x { 7 - h } else { h };
+                let dy = if flip_y { 7 - v } else { v };
+                let color = BLENDING[pixel][blend];
+                self.write(value, x.wrapping_add(dx), y.wrapping_add(dy), color);
+            }
+        }
+    }
+}
+
+/// The screen device, usually installed in slot 2.
+pub struct ScreenDevice {
+    pixels: Arc<Mutex<Framebuffer>>,
+    /// The size being written to the width and height ports, applied by their low bytes.
+    requested: (u16, u16),
+    auto: u8,
+    x: u16,
+    y: u16,
+    addr: u16,
+}
+
+impl ScreenDevice {
+    pub fn new(width: u16, height: u16) -> Self {
+        ScreenDevice {
+            pixels: Arc::new(Mutex::new(Framebuffer::new(width, height))),
+            requested: (width, height),
+            auto: 0,
+            x: 0,
+            y: 0,
+            addr: 0,
+        }
+    }
+
+    /// The screen's pixels, for devices drawing on it such as
+    /// [`crate::devices::DebugTextDevice`].
+    pub fn framebuffer(&self) -> Arc<Mutex<Framebuffer>> {
+        self.pixels.clone()
+    }
+
+    fn pixels(&self) -> MutexGuard<'_, Framebuffer> {
+        // a panic while drawing leaves the pixels as they were, still fine to show
+        self.pixels.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn width(&self) -> u16 {
+        self.pixels().width
+    }
+
+    pub fn height(&self) -> u16 {
+        self.pixels().height
+    }
+
+    /// The palette index at `x`, `y`: the foreground if it is set, the background otherwise.
+    pub fn pixel(&self, x: u16, y: u16) -> u8 {
+        self.pixels().pixel(x, y)
+    }
+
+    /// The screen row by row in `format`, coloring palette indices with the RGB colors of
+    /// `palette`, e.g. [`crate::uxn::Uxn::palette`].
+    pub fn render_frame(&self, palette: &[[u8; 3]; 4], format: PixelFormat) -> Vec<u8> {
+        let pixels = self.pixels();
+        let size = pixels.width as usize * pixels.height as usize;
+        let mut out = Vec::with_capacity(match format {
+            PixelFormat::Indexed8 => size,
+            _ => size * 4,
+        });
+        for y in 0..pixels.height {
+            for x in 0..pixels.width {
+                let color = pixels.pixel(x, y);
+                let [r, g, b] = palette[color as usize];
+                match format {
+                    PixelFormat::Argb8888 => out.extend([0xff, r, g, b]),
+                    PixelFormat::Rgba8888 => out.extend([r, g, b, 0xff]),
+                    PixelFormat::Indexed8 => out.push(color),
+                }
+            }
+        }
+        out
+    }
+
+    /// Changes the size of the screen, keeping the pixels of the part both sizes share.
+    pub fn resize(&mut self, width: u16, height: u16) {
+        self.pixels().resize(width, height);
+        self.requested = (width, height);
+    }
+
+    /// Resizes to the size written to the ports, unless it is out of [`SIZES`].
+    fn request_resize(&mut self) {
+        let (width, height) = self.requested;
+        if SIZES.contains(&width) && SIZES.contains(&height) {
+            self.resize(width, height);
+        } else {
+            self.requested = (self.width(), self.height());
+        }
+    }
+
+    /// Whether anything was drawn since the last call.
+    pub fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.pixels().dirty)
+    }
+
+    /// Draws a 1bpp 8x8 `tile`, see [`Framebuffer::draw_tile`].
+    pub fn draw_tile(&mut self, tile: &[u8; 8], x: u16, y: u16, value: u8) {
+        self.pixels().draw_tile(tile, x, y, value);
+    }
+
+    fn draw_pixel(&mut self, value: u8) {
+        if value & 0x80 != 0 {
+            self.pixels().fill(self.x, self.y, value);
+            return;
+        }
+        self.pixels().write(value, self.x, self.y, value & 0x03);
+        if self.auto & 0x01 != 0 {
+            self.x = self.x.wrapping_add(1);
+        }
+        if self.auto & 0x02 != 0 {
+            self.y = self.y.wrapping_add(1);
+        }
+    }
+
+    /// Draws the sprite at the address port, repeated by the auto byte's length nibble.
+    fn draw_sprite(&mut self, value: u8, ram: &[u8; 65536]) {
+        let two_bpp = value & 0x80 != 0;
+        let length = self.auto >> 4;
+        let dx = ((self.auto & 0x01) as u16) << 3;
+        let dy = ((self.auto & 0x02) as u16) << 2;
+        let addr_step = if self.auto & 0x04 != 0 {
+            if two_bpp {
+                16
+            } else {
+                8
+            }
+        } else {
+            0
+        };
+        let mut pixels = self.pixels.lock().unwrap_or_else(PoisonError::into_inner);
+        for i in 0..=length as u16 {
+            // the repeated tiles go across the auto direction
+            let x = self.x.wrapping_add(dy.wrapping_mul(i));
+            let y = self.y.wrapping_add(dx.wrapping_mul(i));
+            let addr = self.addr;
+            pixels.blit(|i| ram[addr.wrapping_add(i) as usize], x, y, value);
+            self.addr = self.addr.wrapping_add(addr_step);
+        }
+        self.x = self.x.wrapping_add(dx);
+        self.y = self.y.wrapping_add(dy);
+    }
+}
+
+impl Default for ScreenDevice {
+    fn default() -> Self {
+        Self::new(64 * 8, 40 * 8)
+    }
+}
+
+pub(crate) fn set_high(short: &mut u16, value: u8) {
+    *short = (*short & 0x00ff) | (value as u16) << 8;
+}
+
+pub(crate) fn set_low(short: &mut u16, value: u8) {
+    *short = (*short & 0xff00) | value as u16;
+}
+
+impl Device for ScreenDevice {
+    fn dei(&self, port: PortAddress) -> ExecutionResult<u8> {
+        match port {
+            PORT_WIDTH => Ok((self.width() >> 8) as u8),
+            0x03 => Ok(self.width() as u8),
+            PORT_HEIGHT => Ok((self.height() >> 8) as u8),
+            0x05 => Ok(self.height() as u8),
+            PORT_X => Ok((self.x >> 8) as u8),
+            0x09 => Ok(self.x as u8),
+            PORT_Y => Ok((self.y >> 8) as u8),
+            0x0b => Ok(self.y as u8),
+            PORT_ADDR => Ok((self.addr >> 8) as u8),
+            0x0d => Ok(self.addr as u8),
+            _ => Err(UxnError::Device {
+                name: self.name(),
+                reason: "unhandled port",
+            }),
+        }
+    }
+
+    fn deo(&mut self, port: PortAddress, value: u8) -> ExecutionResult<()> {
+        match port {
+            // like varvara, writing the low byte resizes the screen
+            PORT_WIDTH => set_high(&mut self.requested.0, value),
+            0x03 => {
+                set_low(&mut self.requested.0, value);
+                self.request_resize();
+            }
+            PORT_HEIGHT => set_high(&mut self.requested.1, value),
+            0x05 => {
+                set_low(&mut self.requested.1, value);
+                self.request_resize();
+            }
+            PORT_AUTO => self.auto = value,
+            PORT_X => set_high(&mut self.x, value),
+            0x09 => set_low(&mut self.x, value),
+            PORT_Y => set_high(&mut self.y, value),
+            0x0b => set_low(&mut self.y, value),
+            PORT_ADDR => set_high(&mut self.addr, value),
+            0x0d => set_low(&mut self.addr, value),
+            PORT_PIXEL => self.draw_pixel(value),
+            _ => {
+                return Err(UxnError::Device {
+                    name: self.name(),
+                    reason: "unhandled port",
+                })
+            }
+        }
+        Ok(())
+    }
+
+    fn deo_ram(&mut self, port: PortAddress, value: u8, ram: &mut Ram) -> ExecutionResult<()> {
+        match port {
+            PORT_SPRITE => {
+                self.draw_sprite(value, ram.bytes());
+                Ok(())
+            }
+            _ => self.deo(port, value),
+        }
+    }
+
+    // the position and address registers move as the screen draws
+    fn dei_mask(&self) -> u16 {
+        0x3f3c
+    }
+
+    fn deo_mask(&self) -> u16 {
+        0xff7c
+    }
+
+    fn name(&self) -> &'static str {
+        "screen"
+    }
+}
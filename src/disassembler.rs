@@ -0,0 +1,255 @@
+use crate::uxn::{decode_byte, InstructionMode, Opcode, JCI, JMI};
+use std::collections::HashMap;
+
+/// Label names by address, as loaded from a `.sym` file.
+pub type Symbols = HashMap<u16, String>;
+
+/// Mode suffix of a mnemonic, in uxntal order: `2`, `k`, `r`.
+pub fn mode_suffix(mode: InstructionMode) -> String {
+    let mut suffix = String::new();
+    if mode.is_short() {
+        suffix.push('2');
+    }
+    if mode.is_keep() {
+        suffix.push('k');
+    }
+    if mode.is_return() {
+        suffix.push('r');
+    }
+    suffix
+}
+
+fn read(memory: &[u8; 65536], addr: u16) -> u8 {
+    memory[addr as usize]
+}
+
+/// An instruction decoded from raw bytes, see [`decode_instruction`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct DecodedInstruction {
+    pub opcode: Opcode,
+    pub mode: InstructionMode,
+    /// The value of a `LIT`, or the offset of an immediate jump, `LIT` with mode flags but
+    /// without keep.
+    pub operand: Option<u16>,
+}
+
+/// Decodes the instruction at the start of `bytes`, returning it and its length in bytes.
+/// `None` if `bytes` is empty or ends before the instruction's operand.
+pub fn decode_instruction(bytes: &[u8]) -> Option<(DecodedInstruction, usize)> {
+    let (&byte, rest) = bytes.split_first()?;
+    let (opcode, mode) = decode_byte(byte);
+    let width = match opcode {
+        Opcode::LIT if mode.is_keep() && mode.is_short() => 2,
+        Opcode::LIT if mode.is_keep() => 1,
+        Opcode::LIT if mode != InstructionMode::None => 2,
+        _ => 0,
+    };
+    let operand = match width {
+        0 => None,
+        1 => Some(*rest.first()? as u16),
+        _ => Some(u16::from_be_bytes([*rest.first()?, *rest.get(1)?])),
+    };
+    let decoded = DecodedInstruction {
+        opcode,
+        mode,
+        operand,
+    };
+    Some((decoded, 1 + width))
+}
+
+/// Disassembles the instruction at `addr`, returning its uxntal source and its length in bytes.
+///
+/// Literal shorts matching a symbol are shown as `;label`, and literal bytes feeding a relative
+/// jump whose target matches a symbol as `,label`. Immediate jumps show their target, e.g.
+/// `JCI 0123`, or its symbol.
+pub fn disassemble_at(memory: &[u8; 65536], addr: u16, symbols: &Symbols) -> (String, u16) {
+    let window = [0, 1, 2].map(|i| read(memory, addr.wrapping_add(i)));
+    // the window holds the longest instruction
+    let (decoded, len) = decode_instruction(&window).unwrap();
+    let (opcode, mode) = (decoded.opcode, decoded.mode);
+    let len = len as u16;
+
+    let value = match decoded.operand {
+        Some(value) if opcode == Opcode::LIT => value,
+        _ if opcode == Opcode::LIT => return ("BRK".to_string(), 1),
+        _ => return (format!("{:?}{}", opcode, mode_suffix(mode)), 1),
+    };
+    if !mode.is_keep() {
+        let name = match window[0] {
+            JCI => "JCI",
+            JMI => "JMI",
+            _ => "JSI",
+        };
+        let target = addr.wrapping_add(3).wrapping_add(value);
+        let text = match symbols.get(&target) {
+            Some(label) => format!("{} {}", name, label),
+            None => format!("{} {:04x}", name, target),
+        };
+        return (text, len);
+    }
+
+    let is_return = mode.is_return();
+    if mode.is_short() {
+        let text = match symbols.get(&value) {
+            Some(name) if !is_return => format!(";{}", name),
+            _ if is_return => format!("LIT2r {:04x}", value),
+            _ => format!("#{:04x}", value),
+        };
+        return (text, len);
+    }
+
+    let (next, next_mode) = decode_byte(window[2]);
+    let is_relative_jump = matches!(next, Opcode::JMP | Opcode::JCN | Opcode::JSR)
+        && next_mode == InstructionMode::None;
+    // relative jumps are taken from the address after the jump instruction
+    let target = addr.wrapping_add(3).wrapping_add(value as u8 as i8 as u16);
+    let text = match symbols.get(&target) {
+        Some(name) if is_relative_jump && !is_return => format!(",{}", name),
+        _ if is_return => format!("LITr {:02x}", value),
+        _ => format!("#{:02x}", value),
+    };
+    (text, len)
+}
+
+/// Disassembles the instructions from `start` up to (excluding) `end`.
+pub fn disassemble(
+    memory: &[u8; 65536],
+    start: u16,
+    end: u16,
+    symbols: &Symbols,
+) -> Vec<(u16, String)> {
+    let mut lines = vec![];
+    let mut addr = start;
+    while addr < end {
+        let (text, len) = disassemble_at(memory, addr, symbols);
+        lines.push((addr, text));
+        match addr.checked_add(len) {
+            Some(next) => addr = next,
+            None => break,
+        }
+    }
+    lines
+}
+
+fn text_line(out: &mut String, addr: u16, bytes: &[u8], text: &str) {
+    let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    out.push_str(&format!("{:04x}  {:<11}  {}\n", addr, hex.join(" "), text));
+}
+
+/// Renders the memory from `start` up to (excluding) `end` as text: a line per instruction with
+/// its address and bytes, e.g. `0100  80 01        #01`, preceded by `@name` lines at symbols.
+///
+/// Bytes that can't be code are shown as data, up to four per line: `0104  ff 81        .db ff
+/// 81`. These are the bytes following a `BRK` or a jump up to the next symbol, and an
+/// instruction cut off by `end` or by a symbol.
+pub fn disassemble_text(memory: &[u8; 65536], start: u16, end: u16, symbols: &Symbols) -> String {
+    let mut out = String::new();
+    let mut addr = start;
+    let mut in_data = false;
+    while addr < end {
+        if let Some(name) = symbols.get(&addr) {
+            out.push_str(&format!("@{}\n", name));
+            in_data = false;
+        }
+
+        let byte = read(memory, addr);
+        let (text, len) = disassemble_at(memory, addr, symbols);
+        let fits = addr as usize + len as usize <= end as usize
+            && (1..len).all(|i| !symbols.contains_key(&addr.wrapping_add(i)));
+        if in_data || !fits {
+            // data runs up to the next symbol
+            let mut data_end = addr + 1;
+            while data_end < end && data_end - addr < 4 && !symbols.contains_key(&data_end) {
+                data_end += 1;
+            }
+            let bytes = &memory[addr as usize..data_end as usize];
+            let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+            text_line(&mut out, addr, bytes, &format!(".db {}", hex.join(" ")));
+            addr = data_end;
+            continue;
+        }
+
+        let bytes = &memory[addr as usize..addr as usize + len as usize];
+        text_line(&mut out, addr, bytes, &text);
+        in_data = matches!(byte, 0x00 | JMI) || decode_byte(byte).0 == Opcode::JMP;
+        match addr.checked_add(len) {
+            Some(next) => addr = next,
+            None => break,
+        }
+    }
+    out
+}
+
+/// Reads a `.sym` file, as written by [`crate::assembler::Assembled::symbol_file`]. A truncated
+/// last entry is ignored.
+pub fn parse_symbol_file(data: &[u8]) -> Vec<(u16, String)> {
+    let mut symbols = vec![];
+    let mut rest = data;
+    while rest.len() > 2 {
+        let address = u16::from_be_bytes([rest[0], rest[1]]);
+        let Some(len) = rest[2..].iter().position(|b| *b == 0) else {
+            break;
+        };
+        let name = String::from_utf8_lossy(&rest[2..2 + len]).into_owned();
+        symbols.push((address, name));
+        rest = &rest[2 + len + 1..];
+    }
+    symbols
+}
+
+#[test]
+fn parse_symbols() {
+    let data = b"\x01\x00main\x00\x01\x03main/loop\x00\x02";
+    assert_eq!(
+        parse_symbol_file(data),
+        vec![
+            (0x100, "main".to_string()),
+            (0x103, "main/loop".to_string())
+        ]
+    );
+}
+
+#[test]
+fn decode_instructions() {
+    let decoded = |opcode, mode, operand| DecodedInstruction {
+        opcode,
+        mode,
+        operand,
+    };
+    assert_eq!(
+        decode_instruction(&[0xa0, 0x12, 0x34, 0x01]),
+        Some((
+            decoded(
+                Opcode::LIT,
+                InstructionMode::Keep | InstructionMode::Short,
+                Some(0x1234)
+            ),
+            3
+        ))
+    );
+    assert_eq!(
+        decode_instruction(&[0xb8]),
+        Some((
+            decoded(
+                Opcode::ADD,
+                InstructionMode::Keep | InstructionMode::Short,
+                None
+            ),
+            1
+        ))
+    );
+    assert_eq!(
+        decode_instruction(&[0x00, 0xff]),
+        Some((decoded(Opcode::LIT, InstructionMode::None, None), 1))
+    );
+    assert_eq!(
+        decode_instruction(&[0x20, 0xff, 0xfd]),
+        Some((
+            decoded(Opcode::LIT, InstructionMode::Short, Some(0xfffd)),
+            3
+        ))
+    );
+    assert_eq!(decode_instruction(&[]), None);
+    // the operand is cut off
+    assert_eq!(decode_instruction(&[0xa0, 0x12]), None);
+}
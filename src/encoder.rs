@@ -0,0 +1,181 @@
+//! An assembler core that doesn't allocate, for assembling small snippets on targets without a
+//! heap: it splits the source into words and encodes instructions, literals and raw data into a
+//! buffer provided by the caller. Labels, references and padding need [`crate::assembler`],
+//! which resolves them in a separate, allocating pass.
+
+use crate::uxn::{encode_byte, InstructionMode, Opcode};
+use core::fmt;
+
+/// Why a snippet could not be encoded. `at` is the byte offset of the word in the source.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum EncodeError {
+    /// Not an instruction, a literal or raw data.
+    Unrecognized { at: usize },
+    /// A label, a reference or padding, which need the allocating assembler.
+    NeedsAssembler { at: usize },
+    /// The output buffer is too small.
+    BufferFull { at: usize },
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodeError::Unrecognized { at } => write!(f, "unrecognized word at offset {}", at),
+            EncodeError::NeedsAssembler { at } => {
+                write!(f, "labels and padding at offset {} need the assembler", at)
+            }
+            EncodeError::BufferFull { at } => write!(f, "buffer full at offset {}", at),
+        }
+    }
+}
+
+impl std::error::Error for EncodeError {}
+
+/// The words of uxntal source with their byte offsets, skipping whitespace, `( comments )` and
+/// the `[ ]` grouping runes.
+pub struct Words<'a> {
+    src: &'a str,
+    offset: usize,
+}
+
+impl<'a> Words<'a> {
+    pub fn new(src: &'a str) -> Self {
+        Words { src, offset: 0 }
+    }
+}
+
+impl<'a> Iterator for Words<'a> {
+    type Item = (usize, &'a str);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let bytes = self.src.as_bytes();
+        let mut depth = 0;
+        while self.offset < bytes.len() {
+            match bytes[self.offset] {
+                b'(' => depth += 1,
+                b')' if depth > 0 => depth -= 1,
+                _ if depth > 0 => {}
+                c if c.is_ascii_whitespace() => {}
+                _ => {
+                    let start = self.offset;
+                    let len = bytes[start..]
+                        .iter()
+                        .position(|c| c.is_ascii_whitespace())
+                        .unwrap_or(bytes.len() - start);
+                    self.offset += len;
+                    match &self.src[start..start + len] {
+                        "[" | "]" => continue,
+                        word => return Some((start, word)),
+                    }
+                }
+            }
+            self.offset += 1;
+        }
+        None
+    }
+}
+
+fn hex(digits: &str) -> Option<u16> {
+    if digits.is_empty() || !digits.bytes().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    u16::from_str_radix(digits, 16).ok()
+}
+
+fn mode_flags(flags: &str) -> Option<InstructionMode> {
+    let mut mode = InstructionMode::None;
+    for flag in flags.chars() {
+        let bit = match flag {
+            '2' => InstructionMode::Short,
+            'k' => InstructionMode::Keep,
+            'r' => InstructionMode::Return,
+            _ => return None,
+        };
+        if mode.contains(bit) {
+            return None;
+        }
+        mode |= bit;
+    }
+    Some(mode)
+}
+
+/// Encodes a single word into `out`, returning the number of bytes written, or `None` if the
+/// word isn't an instruction, a literal or raw data. `out` must hold at least three bytes.
+fn encode_word(word: &str, out: &mut [u8]) -> Option<usize> {
+    if let Some(digits) = word.strip_prefix('#') {
+        let value = hex(digits)?;
+        return match digits.len() {
+            2 => {
+                out[..2].copy_from_slice(&[
+                    encode_byte(Opcode::LIT, InstructionMode::Keep),
+                    value as u8,
+                ]);
+                Some(2)
+            }
+            4 => {
+                out[0] = encode_byte(Opcode::LIT, InstructionMode::Keep | InstructionMode::Short);
+                out[1..3].copy_from_slice(&value.to_be_bytes());
+                Some(3)
+            }
+            _ => None,
+        };
+    }
+    if word.len() >= 3 && word.as_bytes()[..3].iter().all(|c| c.is_ascii_uppercase()) {
+        let mode = mode_flags(&word[3..])?;
+        let byte = match &word[..3] {
+            "BRK" if mode == InstructionMode::None => 0x00,
+            "BRK" => return None,
+            // like in uxntal, the value of a `LIT` follows as raw data
+            "LIT" => encode_byte(Opcode::LIT, mode | InstructionMode::Keep),
+            mnemonic => encode_byte(mnemonic.parse().ok()?, mode),
+        };
+        out[0] = byte;
+        return Some(1);
+    }
+    // raw data is lowercase, so that it can't be confused with a mnemonic
+    if !word.bytes().all(|c| matches!(c, b'0'..=b'9' | b'a'..=b'f')) {
+        return None;
+    }
+    let value = hex(word)?;
+    match word.len() {
+        2 => {
+            out[0] = value as u8;
+            Some(1)
+        }
+        4 => {
+            out[..2].copy_from_slice(&value.to_be_bytes());
+            Some(2)
+        }
+        _ => None,
+    }
+}
+
+/// Encodes `src`, a snippet of instructions, `#` literals, raw hex and `"ascii` data, into the
+/// start of `out` without allocating. Returns the number of bytes written.
+pub fn encode_into(src: &str, out: &mut [u8]) -> Result<usize, EncodeError> {
+    let mut len = 0;
+    for (at, word) in Words::new(src) {
+        if let Some(text) = word.strip_prefix('"') {
+            let end = len + text.len();
+            if text.is_empty() {
+                return Err(EncodeError::Unrecognized { at });
+            }
+            out.get_mut(len..end)
+                .ok_or(EncodeError::BufferFull { at })?
+                .copy_from_slice(text.as_bytes());
+            len = end;
+            continue;
+        }
+        if word.starts_with(['@', '&', ';', ',', '.', ':', '=', '|', '$', '%']) {
+            return Err(EncodeError::NeedsAssembler { at });
+        }
+
+        let mut bytes = [0u8; 3];
+        let n = encode_word(word, &mut bytes).ok_or(EncodeError::Unrecognized { at })?;
+        out.get_mut(len..len + n)
+            .ok_or(EncodeError::BufferFull { at })?
+            .copy_from_slice(&bytes[..n]);
+        len += n;
+    }
+    Ok(len)
+}
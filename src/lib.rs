@@ -0,0 +1,11 @@
+#[macro_use]
+extern crate custom_derive;
+#[macro_use]
+extern crate enum_derive;
+
+pub mod assembler;
+pub mod clock;
+pub mod devices;
+pub mod disassembler;
+pub mod encoder;
+pub mod uxn;
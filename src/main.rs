@@ -1,35 +1,179 @@
-#[macro_use]
-extern crate custom_derive;
-#[macro_use]
-extern crate enum_derive;
+use std::path::{Path, PathBuf};
+use std::{env, fs, process};
+use uxn_rs::assembler::{assemble, assemble_strict, write_output, OutputFormat, PAGE_PROGRAM};
+use uxn_rs::devices::{ConsoleDevice, CONSOLE};
+use uxn_rs::disassembler::parse_symbol_file;
+use uxn_rs::uxn::Uxn;
 
-mod assembler;
-mod uxn;
+const USAGE: &str =
+    "usage: uxn assemble <input.tal> [-o <output.rom>] [--sym] [--format bin|hex] [--strict]
+       uxn disasm <input.rom> [--sym <input.rom.sym>]
+       uxn run <input.rom>";
 
-use crate::uxn::{InstructionMode, Opcode, Uxn};
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2)
+}
 
-fn main() {
-    let mut uxn = Uxn::new();
-    let lit = (Opcode::LIT as u8) | u8::from(InstructionMode::Keep);
-    uxn.boot();
-    uxn.load_program(
-        &[
-            lit,
-            0x10,
-            Opcode::DUP as u8,
-            lit,
-            0x20,
-            Opcode::ADD as u8,
-            lit,
-            0xff,
-            lit,
-            0x0f,
-            Opcode::DEO as u8,
-            0x00,
-        ],
-        0x100,
+/// `uxn assemble input.tal -o output.rom [--sym] [--format bin|hex] [--strict]`
+fn assemble_command(args: &[String]) -> i32 {
+    let mut input: Option<&str> = None;
+    let mut output: Option<PathBuf> = None;
+    let mut write_symbols = false;
+    let mut strict = false;
+    let mut format = OutputFormat::Binary;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = Some(args.next().unwrap_or_else(|| usage()).into()),
+            "--sym" => write_symbols = true,
+            "--strict" => strict = true,
+            "--format" => {
+                format = match args.next().map(String::as_str) {
+                    Some("bin") => OutputFormat::Binary,
+                    Some("hex") => OutputFormat::IntelHex,
+                    _ => usage(),
+                }
+            }
+            _ if input.is_none() => input = Some(arg),
+            _ => usage(),
+        }
+    }
+    let input = input.unwrap_or_else(|| usage());
+    let extension = match format {
+        OutputFormat::Binary => "rom",
+        OutputFormat::IntelHex => "hex",
+    };
+    let output = output.unwrap_or_else(|| Path::new(input).with_extension(extension));
+
+    let src = match fs::read_to_string(input) {
+        Ok(src) => src,
+        Err(e) => {
+            eprintln!("error: could not read {}: {}", input, e);
+            return 1;
+        }
+    };
+    let result = if strict {
+        assemble_strict(&src)
+    } else {
+        assemble(&src)
+    };
+    let assembled = match result {
+        Ok(assembled) => assembled,
+        Err(e) => {
+            eprint!("{}", e.render(input, &src));
+            return 1;
+        }
+    };
+
+    let mut out = vec![];
+    write_output(&assembled, format, &mut out).expect("writing to memory cannot fail");
+    if let Err(e) = fs::write(&output, out) {
+        eprintln!("error: could not write {}: {}", output.display(), e);
+        return 1;
+    }
+    if write_symbols {
+        let sym = PathBuf::from(format!("{}.sym", output.display()));
+        if let Err(e) = fs::write(&sym, assembled.symbol_file()) {
+            eprintln!("error: could not write {}: {}", sym.display(), e);
+            return 1;
+        }
+    }
+    eprintln!(
+        "Assembled {} in {} bytes, {} labels.",
+        output.display(),
+        assembled.rom.len(),
+        assembled.symbols.len()
     );
-    let ret = uxn.eval(0x100);
+    0
+}
+
+/// `uxn disasm input.rom [--sym input.rom.sym]`
+fn disasm_command(args: &[String]) -> i32 {
+    let mut input: Option<&str> = None;
+    let mut sym: Option<&str> = None;
 
-    println!("{:?}", ret.unwrap());
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--sym" => sym = Some(args.next().unwrap_or_else(|| usage())),
+            _ if input.is_none() => input = Some(arg),
+            _ => usage(),
+        }
+    }
+    let input = input.unwrap_or_else(|| usage());
+
+    let rom = match fs::read(input) {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("error: could not read {}: {}", input, e);
+            return 1;
+        }
+    };
+    let symbols = match sym.map(fs::read) {
+        Some(Ok(data)) => parse_symbol_file(&data),
+        Some(Err(e)) => {
+            eprintln!("error: could not read {}: {}", sym.unwrap_or_default(), e);
+            return 1;
+        }
+        None => vec![],
+    };
+
+    let mut uxn = match Uxn::try_from(rom.as_slice()) {
+        Ok(uxn) => uxn,
+        Err(e) => {
+            eprintln!("error: cannot load {}: {}", input, e);
+            return 1;
+        }
+    };
+    uxn.load_symbols(symbols.clone());
+    let end = (PAGE_PROGRAM as usize + rom.len()) as u16;
+    for (address, text) in uxn.disassemble(PAGE_PROGRAM, end) {
+        for (_, name) in symbols.iter().filter(|(a, _)| *a == address) {
+            println!("@{}", name);
+        }
+        println!("{:04x}  {}", address, text);
+    }
+    0
+}
+
+/// `uxn run input.rom`: runs the reset vector with a console, exiting with the ROM's exit code.
+fn run_command(args: &[String]) -> i32 {
+    let input = match args {
+        [input] => input,
+        _ => usage(),
+    };
+    let rom = match fs::read(input) {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("error: could not read {}: {}", input, e);
+            return 1;
+        }
+    };
+    let mut uxn = Uxn::new();
+    let entry = match uxn.boot_rom(&rom) {
+        Ok(entry) => entry,
+        Err(_) => {
+            eprintln!("error: {} is too large to be a ROM", input);
+            return 1;
+        }
+    };
+    uxn.set_device(CONSOLE, Box::new(ConsoleDevice::new()));
+    if let Err(fault) = uxn.eval(entry) {
+        eprintln!("error: {}", fault);
+        return 1;
+    }
+    uxn.exit_code().unwrap_or(0) as i32
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let code = match args.first().map(String::as_str) {
+        Some("assemble") => assemble_command(&args[1..]),
+        Some("disasm") => disasm_command(&args[1..]),
+        Some("run") => run_command(&args[1..]),
+        _ => usage(),
+    };
+    process::exit(code);
 }
@@ -1,10 +1,20 @@
 extern crate alloc;
 
+use crate::assembler::AsmError;
+use crate::clock::{Clock, SystemClock};
+use crate::devices::{self, console, AudioDevice, ConsoleDevice, ConsoleInputKind, ScreenDevice};
+use crate::disassembler::{self, mode_suffix, Symbols};
 use alloc::boxed::Box;
 use bitmask_enum::bitmask;
+use core::any::Any;
 use core::convert::From;
+use core::fmt;
 use core::result::Result;
 use core::result::Result::{Err, Ok};
+use std::collections::{BTreeSet, VecDeque};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 // description of the varvara virtual computer: https://wiki.xxiivv.com/site/varvara.html
 // high level page of the VM: https://wiki.xxiivv.com/site/uxn.html
@@ -24,7 +34,279 @@ use core::result::Result::{Err, Ok};
 
 pub type PortAddress = u8;
 pub type InstructionPointer = u16;
-pub type ExecutionResult<T> = Result<T, &'static str>;
+pub type ExecutionResult<T> = Result<T, UxnError>;
+
+/// Why an instruction could not be executed.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum UxnError {
+    StackUnderflow,
+    /// A push to a full stack, whose pointer was `ptr`.
+    StackOverflow {
+        stack: StackId,
+        ptr: usize,
+    },
+    DivisionByZero,
+    /// A device rejected a read or write. `name` is the [`Device::name`] of the device.
+    Device {
+        name: &'static str,
+        reason: &'static str,
+    },
+    /// A read or write to a slot without a device.
+    UnknownDevice(u8),
+    /// Data that doesn't fit in memory.
+    OutOfBounds,
+    /// An arithmetic result that doesn't fit its operands' width, in
+    /// [`ArithmeticMode::Checked`].
+    ArithmeticOverflow,
+    /// The program counter went below 0x0100 with the execute guard on, see
+    /// [`Uxn::set_exec_guard`].
+    ExecZeroPage(u16),
+    /// The program counter reached a byte that was never written, with the entry guard on,
+    /// see [`Uxn::set_entry_guard`].
+    ExecUninitialized(u16),
+    /// A byte that isn't an opcode. Every instruction byte decodes to an instruction, this is
+    /// only returned when converting bytes with [`Opcode::try_from`].
+    BadOpcode(u8),
+}
+
+impl fmt::Display for UxnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UxnError::StackUnderflow => write!(f, "stack underflow"),
+            UxnError::StackOverflow { stack, ptr } => {
+                write!(f, "{} stack overflow at {:02x}", stack, ptr)
+            }
+            UxnError::DivisionByZero => write!(f, "division by zero"),
+            UxnError::Device { name, reason } => write!(f, "{} device: {}", name, reason),
+            UxnError::UnknownDevice(slot) => write!(f, "no device in slot {:x}", slot),
+            UxnError::OutOfBounds => write!(f, "out of memory bounds"),
+            UxnError::ArithmeticOverflow => write!(f, "arithmetic overflow"),
+            UxnError::ExecZeroPage(addr) => write!(f, "executing the zero page at {:04x}", addr),
+            UxnError::ExecUninitialized(addr) => {
+                write!(f, "executing uninitialized memory at {:04x}", addr)
+            }
+            UxnError::BadOpcode(byte) => write!(
+                f,
+                "bad opcode {:#04x} (mode: {}, op-bits: {:#04x})",
+                byte,
+                mode_suffix(InstructionMode::from_instruction(*byte)),
+                byte & 0x1f
+            ),
+        }
+    }
+}
+
+impl UxnError {
+    /// The error with `name` as the device of a [`UxnError::Device`], for errors of the device
+    /// installed under that name.
+    fn in_device(self, name: &'static str) -> Self {
+        match self {
+            UxnError::Device { reason, .. } => UxnError::Device { name, reason },
+            error => error,
+        }
+    }
+}
+
+impl std::error::Error for UxnError {}
+
+/// One of the VM's two stacks.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum StackId {
+    Working,
+    Return,
+}
+
+impl StackId {
+    /// The stack an instruction in `mode` works on.
+    pub fn of(mode: InstructionMode) -> Self {
+        if mode.is_return() {
+            StackId::Return
+        } else {
+            StackId::Working
+        }
+    }
+}
+
+impl fmt::Display for StackId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StackId::Working => write!(f, "working"),
+            StackId::Return => write!(f, "return"),
+        }
+    }
+}
+
+/// What `ADD`, `SUB`, `MUL` and `INC` do with results that don't fit their operands' width.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum ArithmeticMode {
+    /// Wrap around, like uxn.
+    #[default]
+    Wrapping,
+    /// Fail with [`UxnError::ArithmeticOverflow`], to catch mistakes while learning.
+    Checked,
+}
+
+/// A [`UxnError`] with the state of the VM when it happened. The stacks are left as the
+/// faulting instruction left them.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Fault {
+    pub error: UxnError,
+    /// Address of the faulting instruction.
+    pub pc: u16,
+    /// The faulting instruction byte, including its mode flags.
+    pub opcode: u8,
+    pub wst_ptr: usize,
+    pub rst_ptr: usize,
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at {:04x} (opcode {:02x}, wst {:02x}, rst {:02x})",
+            self.error, self.pc, self.opcode, self.wst_ptr, self.rst_ptr
+        )
+    }
+}
+
+impl std::error::Error for Fault {}
+
+/// Why [`Uxn::eval_label`] failed.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum EvalLabelError {
+    /// No loaded symbol has this name.
+    UnknownLabel(String),
+    Fault(Fault),
+}
+
+impl fmt::Display for EvalLabelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalLabelError::UnknownLabel(name) => write!(f, "unknown label `{}`", name),
+            EvalLabelError::Fault(fault) => fault.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for EvalLabelError {}
+
+/// Why [`Uxn::assemble_and_run`] or [`Uxn::run_headless`] failed.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum AppError {
+    Asm(AsmError),
+    Fault(Fault),
+    /// The ROM file couldn't be read.
+    Io {
+        path: PathBuf,
+        kind: io::ErrorKind,
+    },
+    /// The ROM doesn't fit in memory.
+    Load(UxnError),
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::Asm(error) => error.fmt(f),
+            AppError::Fault(fault) => fault.fmt(f),
+            AppError::Io { path, kind } => write!(f, "{}: {}", path.display(), kind),
+            AppError::Load(error) => write!(f, "cannot load the rom: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl From<AsmError> for AppError {
+    fn from(error: AsmError) -> Self {
+        AppError::Asm(error)
+    }
+}
+
+impl From<Fault> for AppError {
+    fn from(fault: Fault) -> Self {
+        AppError::Fault(fault)
+    }
+}
+
+/// How a program run by [`Uxn::assemble_and_run`] ended.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct RunOutcome {
+    /// [`EvalOutcome::Break`], or [`EvalOutcome::Halted`] if the program halted.
+    pub outcome: EvalOutcome,
+    /// The working stack, bottom first.
+    pub wst: Vec<u8>,
+    /// The return stack, bottom first.
+    pub rst: Vec<u8>,
+    /// What the program wrote to the console.
+    pub console: Vec<u8>,
+}
+
+impl From<Fault> for EvalLabelError {
+    fn from(fault: Fault) -> Self {
+        EvalLabelError::Fault(fault)
+    }
+}
+
+/// What stopped the VM after a [`Uxn::step`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum EvalOutcome {
+    /// An instruction was executed, execution can continue.
+    Stepped,
+    /// Reached a `BRK`, the end of a vector.
+    Break,
+    /// The program halted by writing to the system state port.
+    Halted,
+    /// The instruction faulted, the program counter is left after it.
+    Error(Fault),
+    /// Stopped before the instruction at a breakpoint, see [`Uxn::run_to_breakpoint`].
+    Breakpoint(u16),
+}
+
+/// Callbacks made while evaluating, see [`Uxn::eval_with`]. They do nothing by default.
+pub trait EvalHooks {
+    /// Called with the device slot, port and value before a device write.
+    fn on_deo(&mut self, _slot: u8, _port: u8, _value: u8) {}
+
+    /// Called with the device slot and port before a device read. Returning `Some` replaces the
+    /// read, the device isn't asked.
+    fn on_dei(&mut self, _slot: u8, _port: u8) -> Option<u8> {
+        None
+    }
+
+    /// Called after a `JSR` with the address of the called routine.
+    fn on_call(&mut self, _addr: u16) {}
+
+    /// Called after a `JMP2r` with the address it returned to.
+    fn on_return(&mut self, _addr: u16) {}
+}
+
+impl EvalHooks for () {}
+
+struct DeviceHooks<O, I> {
+    on_deo: O,
+    on_dei: I,
+}
+
+impl<O: FnMut(u8, u8, u8), I: FnMut(u8, u8) -> Option<u8>> EvalHooks for DeviceHooks<O, I> {
+    fn on_deo(&mut self, slot: u8, port: u8, value: u8) {
+        (self.on_deo)(slot, port, value)
+    }
+
+    fn on_dei(&mut self, slot: u8, port: u8) -> Option<u8> {
+        (self.on_dei)(slot, port)
+    }
+}
+
+/// What happened during a [`Uxn::run_frame`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct FrameResult {
+    pub halted: bool,
+    /// Whether the screen was drawn to, and should be presented.
+    pub screen_dirty: bool,
+    /// Samples played by the audio channels, summed over the channels.
+    pub audio_samples: usize,
+}
 
 #[bitmask(u8)]
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
@@ -35,6 +317,49 @@ pub enum InstructionMode {
     Short = 0x20,
 }
 
+impl InstructionMode {
+    /// The mode flags of an instruction byte, without the opcode in its low five bits.
+    #[inline(always)]
+    pub fn from_instruction(byte: u8) -> Self {
+        Self::from(byte & 0xe0)
+    }
+
+    #[inline(always)]
+    pub fn is_short(&self) -> bool {
+        self.contains(InstructionMode::Short)
+    }
+
+    #[inline(always)]
+    pub fn is_keep(&self) -> bool {
+        self.contains(InstructionMode::Keep)
+    }
+
+    #[inline(always)]
+    pub fn is_return(&self) -> bool {
+        self.contains(InstructionMode::Return)
+    }
+
+    /// The same mode on bytes, for operands that are always a byte such as a zero-page address.
+    #[inline(always)]
+    pub fn as_byte(&self) -> InstructionMode {
+        *self & !InstructionMode::Short
+    }
+
+    /// The same mode on shorts, for operands that are always a short such as an absolute
+    /// address.
+    #[inline(always)]
+    pub fn as_short(&self) -> InstructionMode {
+        *self | InstructionMode::Short
+    }
+
+    /// The same mode operating on the opposite stack, as used by STH and JSR to move values
+    /// between the working and return stacks.
+    #[inline(always)]
+    pub fn other_stack(&self) -> InstructionMode {
+        *self ^ InstructionMode::Return
+    }
+}
+
 custom_derive! {
 #[repr(u8)]
 #[derive(Debug, PartialEq, Eq, Clone, Copy, EnumFromStr)]
@@ -74,41 +399,271 @@ pub enum Opcode {
 }
 }
 
-impl From<u8> for Opcode {
-    fn from(value: u8) -> Self {
-        unsafe { std::mem::transmute(value) }
+impl Opcode {
+    /// Every opcode, in numeric order from `LIT` at 0x00 to `SFT` at 0x1f.
+    pub fn all() -> &'static [Opcode; 32] {
+        use Opcode::*;
+        &[
+            LIT, INC, POP, NIP, SWP, ROT, DUP, OVR, EQU, NEQ, GTH, LTH, JMP, JCN, JSR, STH, LDZ,
+            STZ, LDR, STR, LDA, STA, DEI, DEO, ADD, SUB, MUL, DIV, AND, ORA, EOR, SFT,
+        ]
+    }
+}
+
+/// The opcode in the low five bits of an instruction byte. Bytes with mode flags set are
+/// rejected, see [`decode_byte`] to split them.
+impl TryFrom<u8> for Opcode {
+    type Error = UxnError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Opcode::all()
+            .get(value as usize)
+            .copied()
+            .ok_or(UxnError::BadOpcode(value))
+    }
+}
+
+/// Jumps if the byte on top of the working stack isn't zero, to the offset in the short after it.
+pub const JCI: u8 = 0x20;
+/// Jumps to the offset in the short after it.
+pub const JMI: u8 = 0x40;
+/// Calls the offset in the short after it, pushing the return address on the return stack.
+pub const JSI: u8 = 0x60;
+
+/// The instruction byte for `opcode` with the `mode` flags.
+pub fn encode_byte(opcode: Opcode, mode: InstructionMode) -> u8 {
+    opcode as u8 | (mode.bits() & 0xe0)
+}
+
+/// Splits an instruction byte into its opcode, the low 5 bits, and mode flags, the high 3 bits.
+pub fn decode_byte(byte: u8) -> (Opcode, InstructionMode) {
+    (
+        // five bits always hold an opcode
+        Opcode::try_from(byte & 0x1f).unwrap(),
+        InstructionMode::from_instruction(byte),
+    )
+}
+
+impl Opcode {
+    /// Bytes popped from and pushed to the instruction's stack. In keep mode nothing is popped.
+    /// `JSR` and `STH` push to the other stack, which is not counted. `LIT` without keep is
+    /// `BRK`, or with mode flags the immediate jumps, of which [`JCI`] pops its condition.
+    pub fn stack_effect(&self, mode: InstructionMode) -> (u8, u8) {
+        let w = if mode.is_short() { 2 } else { 1 };
+        let (pops, pushes) = match self {
+            Opcode::LIT if mode == InstructionMode::Short => (1, 0),
+            Opcode::LIT if !mode.is_keep() => (0, 0),
+            Opcode::LIT => (0, w),
+            Opcode::INC => (w, w),
+            Opcode::POP => (w, 0),
+            Opcode::NIP => (2 * w, w),
+            Opcode::SWP => (2 * w, 2 * w),
+            Opcode::ROT => (3 * w, 3 * w),
+            Opcode::DUP => (w, 2 * w),
+            Opcode::OVR => (2 * w, 3 * w),
+            Opcode::EQU | Opcode::NEQ | Opcode::GTH | Opcode::LTH => (2 * w, 1),
+            Opcode::JMP | Opcode::JSR | Opcode::STH => (w, 0),
+            Opcode::JCN => (w + 1, 0),
+            Opcode::LDZ | Opcode::LDR | Opcode::DEI => (1, w),
+            Opcode::STZ | Opcode::STR | Opcode::DEO => (1 + w, 0),
+            Opcode::LDA => (2, w),
+            Opcode::STA => (2 + w, 0),
+            Opcode::ADD
+            | Opcode::SUB
+            | Opcode::MUL
+            | Opcode::DIV
+            | Opcode::AND
+            | Opcode::ORA
+            | Opcode::EOR => (2 * w, w),
+            Opcode::SFT => (1 + w, w),
+        };
+        if mode.is_keep() {
+            (0, pushes)
+        } else {
+            (pops, pushes)
+        }
     }
 }
 
-trait Device {
+/// A varvara device, occupying one of the 16 device slots. Ports are the low nibble of the
+/// device address, so a device in slot 1 sees a `#18 DEO` as a write to port 0x08.
+///
+/// Devices must be `Send` so that a [`Uxn`] can be moved to another thread, e.g. by a server
+/// running many ROMs on a pool of workers.
+pub trait Device: Any + Send {
     fn dei(&self, port: PortAddress) -> ExecutionResult<u8>;
     // fn dei2(&self, port: PortAddress) -> Result<u16, &str>;
     fn deo(&mut self, port: PortAddress, value: u8) -> ExecutionResult<()>;
     // fn deo2(&self, port: PortAddress, value: u16) -> Result<(), &str>;
+
+    /// A write with access to RAM, for devices that read or write memory such as the screen's
+    /// sprite port. This is what the VM calls, and it forwards to [`Device::deo`] by default.
+    fn deo_ram(&mut self, port: PortAddress, value: u8, _ram: &mut Ram) -> ExecutionResult<()> {
+        self.deo(port, value)
+    }
+
+    /// Ports whose reads go through [`Device::dei`], one bit per port. Reads from the other
+    /// ports return the last value written to device memory.
+    fn dei_mask(&self) -> u16 {
+        0xffff
+    }
+
+    /// Ports whose writes trigger [`Device::deo`], one bit per port. Writes always land in
+    /// device memory first, so ports without side effects need no handling.
+    fn deo_mask(&self) -> u16 {
+        0xffff
+    }
+
+    /// A short name for listings and error messages, e.g. `console`.
+    fn name(&self) -> &'static str {
+        "device"
+    }
+
+    /// Called when the VM halts, to flush buffered output or release resources.
+    fn on_halt(&mut self) -> ExecutionResult<()> {
+        Ok(())
+    }
+}
+
+/// The VM's RAM as seen by [`Device::deo_ram`]. Writes go through [`Ram::set`], so that
+/// what a device wrote counts as written for [`Uxn::set_entry_guard`].
+pub struct Ram<'a> {
+    bytes: &'a mut [u8; 65536],
+    written: &'a mut [u64; 1024],
+}
+
+impl Ram<'_> {
+    /// The whole 64kb, for reading.
+    pub fn bytes(&self) -> &[u8; 65536] {
+        self.bytes
+    }
+
+    pub fn get(&self, addr: u16) -> u8 {
+        self.bytes[addr as usize]
+    }
+
+    pub fn set(&mut self, addr: u16, value: u8) {
+        self.bytes[addr as usize] = value;
+        mark_written(self.written, addr as usize);
+    }
+}
+
+#[inline(always)]
+fn mark_written(written: &mut [u64; 1024], addr: usize) {
+    written[addr >> 6] |= 1 << (addr & 63);
+}
+
+impl dyn Device {
+    /// Recovers the concrete type of a device installed with [`Uxn::set_device`].
+    pub fn downcast_ref<T: Device>(&self) -> Option<&T> {
+        (self as &dyn Any).downcast_ref()
+    }
+
+    pub fn downcast_mut<T: Device>(&mut self) -> Option<&mut T> {
+        (self as &mut dyn Any).downcast_mut()
+    }
+}
+
+/// A device slot. The console and the screen are stored by type so that their ports are
+/// direct calls, the other devices are called through the box.
+enum Slot {
+    Console(ConsoleDevice),
+    Screen(ScreenDevice),
+    Other(Box<dyn Device>),
+}
+
+impl Slot {
+    fn new(device: Box<dyn Device>) -> Self {
+        if device.downcast_ref::<ConsoleDevice>().is_some() {
+            Slot::Console(*Self::unbox(device))
+        } else if device.downcast_ref::<ScreenDevice>().is_some() {
+            Slot::Screen(*Self::unbox(device))
+        } else {
+            Slot::Other(device)
+        }
+    }
+
+    fn unbox<T: Device>(device: Box<dyn Device>) -> Box<T> {
+        (device as Box<dyn Any>)
+            .downcast()
+            .expect("checked by Slot::new")
+    }
+
+    fn get(&self) -> &dyn Device {
+        match self {
+            Slot::Console(console) => console,
+            Slot::Screen(screen) => screen,
+            Slot::Other(device) => device.as_ref(),
+        }
+    }
+
+    fn get_mut(&mut self) -> &mut dyn Device {
+        match self {
+            Slot::Console(console) => console,
+            Slot::Screen(screen) => screen,
+            Slot::Other(device) => device.as_mut(),
+        }
+    }
 }
 
 struct NullDevice {}
 
 impl Device for NullDevice {
     fn dei(&self, _port: PortAddress) -> ExecutionResult<u8> {
-        Err("NullDevice::dei")
+        Err(UxnError::Device {
+            name: self.name(),
+            reason: "no device installed",
+        })
     }
     fn deo(&mut self, _port: PortAddress, _value: u8) -> ExecutionResult<()> {
-        Err("NullDevice::deo")
+        Err(UxnError::Device {
+            name: self.name(),
+            reason: "no device installed",
+        })
+    }
+    fn name(&self) -> &'static str {
+        "null"
     }
 }
 
-type StackPointer = u8;
+type StackPointer = usize;
+
+/// Reads the big-endian short at `ptr` in `data`, a stack or the device memory. Every short
+/// the VM keeps outside RAM goes through this and [`write_short_at`].
+#[inline(always)]
+fn read_short_at(data: &[u8], ptr: usize) -> u16 {
+    u16::from_be_bytes([data[ptr], data[ptr + 1]])
+}
+
+/// Writes `v` at `ptr` in `data`, high byte first.
+#[inline(always)]
+fn write_short_at(data: &mut [u8], ptr: usize, v: u16) {
+    data[ptr..ptr + 2].copy_from_slice(&v.to_be_bytes());
+}
 
 struct Stack {
     ptr: StackPointer,
     kptr: StackPointer,
-    data: [u8; 256],
+    data: Vec<u8>,
 }
 
 impl Stack {
-    pub fn print(&self) {
-        println!("Stack: {:?}", self.data);
+    fn new(capacity: usize) -> Self {
+        Stack {
+            ptr: 0,
+            kptr: 0,
+            data: vec![0; capacity],
+        }
+    }
+
+    /// Moves the pointer to `ptr` for the system device, unless it is past the end.
+    fn set_ptr(&mut self, ptr: u8, id: StackId) -> ExecutionResult<()> {
+        let ptr = ptr as StackPointer;
+        if ptr >= self.data.len() {
+            return Err(UxnError::StackOverflow { stack: id, ptr });
+        }
+        self.ptr = ptr;
+        Ok(())
     }
 }
 
@@ -117,472 +672,3001 @@ pub struct Uxn {
     pc: u16,
     wst: Stack,
     rst: Stack,
-    devices: [Box<dyn Device>; 16],
+    dev: [u8; 256],
+    devices: [Slot; 16],
+    /// The `(dei_mask, deo_mask)` of each device, read when it is installed so that ports
+    /// without side effects are plain memory accesses, not calls through the box.
+    port_masks: [(u16, u16); 16],
+    /// One bit per slot with a device installed.
+    installed: u16,
     is_halted: bool,
+    exit_code: u8,
+    symbols: Symbols,
+    input_vectors: VecDeque<u8>,
+    arithmetic: ArithmeticMode,
+    exec_guard: bool,
+    entry_guard: bool,
+    /// One bit per RAM byte written since boot, checked by the entry guard.
+    written: [u64; 1024],
+    /// The address of every instruction run since the trace was enabled.
+    pc_trace: Option<Vec<u16>>,
+    breakpoints: BTreeSet<u16>,
+    clock: Arc<dyn Clock>,
+    /// The clock's samples at the last [`Uxn::run_clocked_frame`].
+    clock_samples: usize,
 }
 
+/// The system device. Its ports:
+///
+/// | port      | name     |                                                  |
+/// |-----------|----------|--------------------------------------------------|
+/// | 0x00-0x01 | vector   | device memory                                    |
+/// | 0x02      | wst      | working stack pointer                            |
+/// | 0x03      | rst      | return stack pointer                             |
+/// | 0x04-0x05 |          | unmapped, accesses fail with a `Device` error     |
+/// | 0x06-0x07 | metadata | device memory, address of [`Uxn::metadata`]      |
+/// | 0x08-0x0d | r, g, b  | device memory, the palette as three shorts       |
+/// | 0x0e      | debug    | writing calls [`Uxn::print`]                     |
+/// | 0x0f      | state    | a non-zero value halts with [`Uxn::exit_code`]   |
+///
+/// Like every port pair, 0x02 is also read and written as a short, the working stack pointer
+/// being its high byte: `#02 DEI2` pushes both pointers at once. With stacks larger than 256
+/// bytes, see [`Uxn::with_stack_size`], reads return the low byte of the pointer. Writing a
+/// pointer past the end of a smaller stack fails with [`UxnError::StackOverflow`].
 impl Device for Uxn {
     fn dei(&self, port: PortAddress) -> ExecutionResult<u8> {
         match port {
-            0x02 => return Ok(self.wst.ptr),
-            0x03 => return Ok(self.rst.ptr),
-            _ => return Err("Uxn::dei"),
+            0x02 => Ok(self.wst.ptr as u8),
+            0x03 => Ok(self.rst.ptr as u8),
+            _ => Err(UxnError::Device {
+                name: self.name(),
+                reason: "unmapped port",
+            }),
         }
     }
 
     fn deo(&mut self, port: PortAddress, value: u8) -> ExecutionResult<()> {
         match port {
-            0x02 => self.wst.ptr = value,
-            0x03 => self.rst.ptr = value,
+            0x02 => self.wst.set_ptr(value, StackId::Working)?,
+            0x03 => self.rst.set_ptr(value, StackId::Return)?,
             0x0e => self.print(),
-            0x0f => self.is_halted = value != 0x00,
-            port if port > 0x07 && port < 0x0e => return Ok(()), // TODO screen palette
-            _ => return Err("Uxn::deo"),
+            // like varvara, a non-zero value halts with the exit code in its low 7 bits
+            0x0f => {
+                self.is_halted = value != 0x00;
+                self.exit_code = value & 0x7f;
+                if self.is_halted {
+                    self.halt_devices()?;
+                }
+            }
+            _ => {
+                return Err(UxnError::Device {
+                    name: self.name(),
+                    reason: "unmapped port",
+                })
+            }
         }
         Ok(())
     }
+
+    fn dei_mask(&self) -> u16 {
+        0x003c
+    }
+
+    fn deo_mask(&self) -> u16 {
+        0xc03c
+    }
+
+    fn name(&self) -> &'static str {
+        "system"
+    }
+}
+
+impl Default for Uxn {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A booted VM with `rom` loaded at 0x0100. Fails when the ROM doesn't fit in memory.
+impl TryFrom<&[u8]> for Uxn {
+    type Error = UxnError;
+
+    fn try_from(rom: &[u8]) -> Result<Self, Self::Error> {
+        let mut uxn = Uxn::new();
+        uxn.boot_rom(rom)?;
+        Ok(uxn)
+    }
+}
+
+/// A booted VM with the assembled source loaded at 0x0100. The source starts at 0x0100 unless
+/// it pads elsewhere.
+impl TryFrom<&str> for Uxn {
+    type Error = AsmError;
+
+    fn try_from(src: &str) -> Result<Self, Self::Error> {
+        let assembled = crate::assembler::assemble_program(src)?;
+        let mut uxn = Uxn::new();
+        // the assembler only emits what fits in memory
+        uxn.boot_rom(&assembled.rom).unwrap();
+        Ok(uxn)
+    }
 }
 
 impl Uxn {
     pub fn new() -> Self {
+        Self::with_stack_size(256)
+    }
+
+    /// A VM whose stacks hold `stack_size` bytes instead of uxn's 256, for experiments. The
+    /// system device's stack pointer ports only see the low byte of larger pointers.
+    pub fn with_stack_size(stack_size: usize) -> Self {
         Uxn {
             ram: [0; 65536],
             pc: 0,
-            wst: Stack {
-                ptr: 0,
-                kptr: 0,
-                data: [0; 256],
-            },
-            rst: Stack {
-                ptr: 0,
-                kptr: 0,
-                data: [0; 256],
-            },
+            wst: Stack::new(stack_size),
+            rst: Stack::new(stack_size),
+            dev: [0; 256],
             devices: [
-                Box::new(NullDevice {}),
-                Box::new(NullDevice {}),
-                Box::new(NullDevice {}),
-                Box::new(NullDevice {}),
-                Box::new(NullDevice {}),
-                Box::new(NullDevice {}),
-                Box::new(NullDevice {}),
-                Box::new(NullDevice {}),
-                Box::new(NullDevice {}),
-                Box::new(NullDevice {}),
-                Box::new(NullDevice {}),
-                Box::new(NullDevice {}),
-                Box::new(NullDevice {}),
-                Box::new(NullDevice {}),
-                Box::new(NullDevice {}),
-                Box::new(NullDevice {}),
+                Slot::Other(Box::new(NullDevice {})),
+                Slot::Other(Box::new(NullDevice {})),
+                Slot::Other(Box::new(NullDevice {})),
+                Slot::Other(Box::new(NullDevice {})),
+                Slot::Other(Box::new(NullDevice {})),
+                Slot::Other(Box::new(NullDevice {})),
+                Slot::Other(Box::new(NullDevice {})),
+                Slot::Other(Box::new(NullDevice {})),
+                Slot::Other(Box::new(NullDevice {})),
+                Slot::Other(Box::new(NullDevice {})),
+                Slot::Other(Box::new(NullDevice {})),
+                Slot::Other(Box::new(NullDevice {})),
+                Slot::Other(Box::new(NullDevice {})),
+                Slot::Other(Box::new(NullDevice {})),
+                Slot::Other(Box::new(NullDevice {})),
+                Slot::Other(Box::new(NullDevice {})),
             ],
+            port_masks: [(0, 0); 16],
+            installed: 0,
             is_halted: false,
+            exit_code: 0,
+            symbols: Symbols::new(),
+            input_vectors: VecDeque::new(),
+            arithmetic: ArithmeticMode::default(),
+            exec_guard: false,
+            entry_guard: false,
+            written: [0; 1024],
+            pc_trace: None,
+            breakpoints: BTreeSet::new(),
+            clock: Arc::new(SystemClock::new()),
+            clock_samples: 0,
         }
     }
 
+    /// A VM timed by `clock` instead of the system's clock.
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
+        let mut uxn = Self::new();
+        uxn.clock_samples = clock.elapsed_samples();
+        uxn.clock = clock;
+        uxn
+    }
+
+    /// The VM's clock, to be shared with devices such as [`devices::DateTimeDevice`].
+    pub fn clock(&self) -> Arc<dyn Clock> {
+        self.clock.clone()
+    }
+
     pub fn boot(&mut self) {
-        let x: u8 = 0;
+        let x = 0;
         let x2: StackPointer = 0;
 
         self.wst.ptr = x2;
         self.wst.ptr = x;
 
-        self.wst.ptr = 0;
-        self.wst.kptr = self.wst.ptr;
-        self.rst.ptr = 0;
-        self.rst.kptr = self.rst.ptr;
+        self.reset_stacks();
 
         self.ram.iter_mut().for_each(|x| *x = 0);
+        self.written = [0; 1024];
+        self.dev.iter_mut().for_each(|x| *x = 0);
         self.pc = 0;
         self.is_halted = false;
+        self.exit_code = 0;
     }
 
-    pub fn load_program(&mut self, program: &[u8], addr: usize) {
-        self.ram[addr..(addr + program.len())].copy_from_slice(program);
+    /// Empties both stacks, leaving RAM, devices and the PC alone, e.g. between routines run
+    /// by a test harness.
+    pub fn reset_stacks(&mut self) {
+        for stack in [&mut self.wst, &mut self.rst] {
+            stack.ptr = 0;
+            stack.kptr = 0;
+        }
     }
 
-    #[inline(always)]
-    pub fn peek(&mut self, addr: usize, mode: InstructionMode) -> ExecutionResult<u16> {
-        if mode.contains(InstructionMode::Short) {
-            Ok((self.ram[addr] as u16) << 8 | self.ram[addr + 1] as u16)
-        } else {
-            Ok(self.ram[addr] as u16)
+    /// Boots the VM and loads `rom` at 0x0100, returning the address to [`Uxn::eval`] to start
+    /// it. Fails when the ROM doesn't fit in memory.
+    pub fn boot_rom(&mut self, rom: &[u8]) -> ExecutionResult<u16> {
+        const ENTRY: u16 = 0x0100;
+        if rom.len() > self.ram.len() - ENTRY as usize {
+            return Err(UxnError::OutOfBounds);
         }
+        self.boot();
+        self.load_program(rom, ENTRY as usize);
+        Ok(ENTRY)
     }
 
-    #[inline(always)]
-    fn get_stack(&mut self, mode: InstructionMode) -> &mut Stack {
-        if mode.contains(InstructionMode::Return) {
-            &mut self.rst
-        } else {
-            &mut self.wst
-        }
+    /// Assembles `src`, which starts at 0x0100 unless it pads elsewhere, and runs it with a
+    /// capturing console until it breaks or halts.
+    pub fn assemble_and_run(src: &str) -> Result<RunOutcome, AppError> {
+        let assembled = crate::assembler::assemble_program(src)?;
+        let mut uxn = Uxn::new();
+        // the assembler only emits what fits in memory
+        let entry = uxn.boot_rom(&assembled.rom).unwrap();
+        uxn.set_device(devices::CONSOLE, Box::new(ConsoleDevice::with_capture()));
+        uxn.eval(entry)?;
+
+        let console = uxn.device(devices::CONSOLE).downcast_ref::<ConsoleDevice>();
+        Ok(RunOutcome {
+            outcome: match uxn.is_halted {
+                true => EvalOutcome::Halted,
+                false => EvalOutcome::Break,
+            },
+            wst: uxn.wst.data[..uxn.wst.ptr].to_vec(),
+            rst: uxn.rst.data[..uxn.rst.ptr].to_vec(),
+            console: console.map_or_else(Vec::new, |console| console.captured().to_vec()),
+        })
     }
 
-    #[inline(always)]
-    pub fn kpop8(&mut self, mode: InstructionMode) -> ExecutionResult<u16> {
-        let mut s = self.get_stack(mode);
-        if s.kptr == 0 {
-            return Err("Stack underflow");
+    /// Runs the ROM at `path` with a capturing console: its reset vector, then its console
+    /// vector with each byte of `input` as stdin and a last [`ConsoleInputKind::Eof`]. Stops
+    /// early when the ROM halts, or with [`EvalOutcome::Stepped`] once `max` instructions ran
+    /// in all. Returns how the last vector ended and what the ROM wrote to the console.
+    pub fn run_headless(
+        path: impl AsRef<Path>,
+        input: &[u8],
+        max: u64,
+    ) -> Result<(EvalOutcome, Vec<u8>), AppError> {
+        let path = path.as_ref();
+        let rom = std::fs::read(path).map_err(|error| AppError::Io {
+            path: path.to_path_buf(),
+            kind: error.kind(),
+        })?;
+        let mut uxn = Uxn::new();
+        uxn.pc = uxn.boot_rom(&rom).map_err(AppError::Load)?;
+        uxn.set_device(devices::CONSOLE, Box::new(ConsoleDevice::with_capture()));
+
+        let mut budget = max;
+        let mut run = |uxn: &mut Uxn| match uxn.eval_budget(budget) {
+            (EvalOutcome::Error(fault), _) => Err(AppError::Fault(fault)),
+            (outcome, used) => {
+                budget -= used;
+                Ok(outcome)
+            }
+        };
+        let mut outcome = run(&mut uxn)?;
+        let events = input
+            .iter()
+            .map(|byte| (*byte, ConsoleInputKind::Stdin))
+            .chain([(0, ConsoleInputKind::Eof)]);
+        for (byte, kind) in events {
+            let vector = uxn.vector(devices::CONSOLE);
+            if outcome != EvalOutcome::Break || vector == 0 {
+                break;
+            }
+            uxn.set_console_input(byte, kind);
+            uxn.pc = vector;
+            outcome = run(&mut uxn)?;
         }
-        let value = s.data[s.kptr as usize];
-        s.kptr -= 1;
-        Ok(value as u16)
+
+        let console = uxn.device(devices::CONSOLE).downcast_ref::<ConsoleDevice>();
+        let output = console.map_or_else(Vec::new, |console| console.captured().to_vec());
+        Ok((outcome, output))
     }
 
-    #[inline(always)]
-    pub fn kpop16(&mut self, mode: InstructionMode) -> ExecutionResult<u16> {
-        let mut s = self.get_stack(mode);
-        if s.kptr <= 1 {
-            return Err("Stack underflow");
-        }
-        s.kptr -= 2;
-        Ok((s.data[s.kptr as usize] as u16) << 8 | s.data[s.kptr as usize + 1] as u16)
+    pub fn load_program(&mut self, program: &[u8], addr: usize) {
+        self.ram[addr..(addr + program.len())].copy_from_slice(program);
+        (addr..addr + program.len()).for_each(|addr| self.mark_written(addr));
     }
 
-    #[inline(always)]
-    pub fn pop8(&mut self, mode: InstructionMode) -> ExecutionResult<u16> {
-        let mut s = self.get_stack(mode);
-        if s.ptr == 0 {
-            return Err("Stack underflow");
+    /// Copies `data` into RAM at `addr`, wrapping around past 0xffff like the VM's own
+    /// accesses. Fails when `data` is larger than the whole memory.
+    pub fn set_ram_slice(&mut self, addr: u16, data: &[u8]) -> ExecutionResult<()> {
+        if data.len() > self.ram.len() {
+            return Err(UxnError::OutOfBounds);
         }
-        s.ptr -= 1;
-        let value = s.data[s.ptr as usize];
-        Ok(value as u16)
+        for (i, byte) in data.iter().enumerate() {
+            self.ram[addr.wrapping_add(i as u16) as usize] = *byte;
+            self.mark_written(addr.wrapping_add(i as u16) as usize);
+        }
+        Ok(())
     }
 
-    #[inline(always)]
-    pub fn pop16(&mut self, mode: InstructionMode) -> ExecutionResult<u16> {
-        let mut s = self.get_stack(mode);
-        if s.ptr <= 1 {
-            return Err("Stack underflow");
-        }
-        s.ptr -= 2;
-        Ok((s.data[s.ptr as usize] as u16) << 8 | s.data[s.ptr as usize + 1] as u16)
+    /// The whole 64kb of RAM, e.g. for memory viewers.
+    pub fn memory(&self) -> &[u8; 65536] {
+        &self.ram
     }
 
-    #[inline(always)]
-    pub fn pop(&mut self, mode: InstructionMode) -> ExecutionResult<u16> {
-        if mode.contains(InstructionMode::Keep) {
-            if mode.contains(InstructionMode::Short) {
-                self.kpop16(mode)
-            } else {
-                self.kpop8(mode)
-            }
-        } else {
-            if mode.contains(InstructionMode::Short) {
-                self.pop16(mode)
-            } else {
-                self.pop8(mode)
-            }
-        }
+    /// Mutable access to RAM, for hosts patching a loaded ROM. As the writes can't be tracked,
+    /// all of RAM counts as written for [`Uxn::set_entry_guard`].
+    pub fn memory_mut(&mut self) -> &mut [u8; 65536] {
+        self.written = [u64::MAX; 1024];
+        &mut self.ram
     }
 
     #[inline(always)]
-    pub fn push8(&mut self, v: u16, mode: InstructionMode) -> ExecutionResult<()> {
-        let mut s = self.get_stack(mode);
-        if s.ptr >= 255 {
-            return Err("Stack overflow");
-        }
-        s.data[s.ptr as usize] = v as u8;
-        s.ptr += 1;
-        Ok(())
+    fn mark_written(&mut self, addr: usize) {
+        mark_written(&mut self.written, addr);
     }
-    #[inline(always)]
+
+    fn is_written(&self, addr: u16) -> bool {
+        self.written[addr as usize >> 6] & (1 << (addr & 63)) != 0
+    }
+
+    /// The width and height of the screen device, as the ROM last set them through its ports.
+    /// `(0, 0)` without a screen device.
+    pub fn screen_size(&self) -> (u16, u16) {
+        self.device(devices::SCREEN)
+            .downcast_ref::<ScreenDevice>()
+            .map_or((0, 0), |screen| (screen.width(), screen.height()))
+    }
+
+    /// The four colors of the system palette as RGB. The palette is the three shorts at ports
+    /// 0x08-0x0d, red, green and blue, with a nibble per color.
+    pub fn palette(&self) -> [[u8; 3]; 4] {
+        let channel = |port: usize, color: usize| {
+            let short = read_short_at(&self.dev, port);
+            ((short >> (12 - 4 * color)) & 0xf) as u8 * 0x11
+        };
+        std::array::from_fn(|color| {
+            [
+                channel(0x08, color),
+                channel(0x0a, color),
+                channel(0x0c, color),
+            ]
+        })
+    }
+
+    /// The metadata the ROM published through the system device's port 0x06, such as its name:
+    /// the bytes from that address up to a NUL. `None` if no metadata was published or it isn't
+    /// terminated before the end of memory.
+    pub fn metadata(&self) -> Option<&[u8]> {
+        let addr = read_short_at(&self.dev, 0x06) as usize;
+        if addr == 0 {
+            return None;
+        }
+        let len = self.ram[addr..].iter().position(|b| *b == 0)?;
+        Some(&self.ram[addr..addr + len])
+    }
+
+    /// A copy of the 256 bytes of device memory, for reproducing device state in tests.
+    pub fn dump_devices(&self) -> [u8; 256] {
+        self.dev
+    }
+
+    /// Restores device memory saved with [`Uxn::dump_devices`]. This only sets the memory seen
+    /// by DEI, the devices themselves are not notified. RAM is untouched, so what counts as
+    /// written for [`Uxn::set_entry_guard`] doesn't change either.
+    pub fn load_devices(&mut self, dev: &[u8; 256]) {
+        self.dev = *dev;
+    }
+
+    /// Loads label names, e.g. from an assembler's symbol table or a `.sym` file, to be shown
+    /// by the disassembler. Only the first label at an address is kept.
+    pub fn load_symbols(&mut self, symbols: Vec<(u16, String)>) {
+        for (address, name) in symbols {
+            self.symbols.entry(address).or_insert(name);
+        }
+    }
+
+    /// The address of the loaded symbol `name`.
+    pub fn label_address(&self, name: &str) -> Option<u16> {
+        self.symbols
+            .iter()
+            .find(|(_, symbol)| *symbol == name)
+            .map(|(address, _)| *address)
+    }
+
+    /// Evaluates from the loaded symbol `name`, see [`Uxn::load_symbols`].
+    pub fn eval_label(&mut self, name: &str) -> Result<(), EvalLabelError> {
+        let address = self
+            .label_address(name)
+            .ok_or_else(|| EvalLabelError::UnknownLabel(name.to_string()))?;
+        Ok(self.eval(address)?)
+    }
+
+    /// Disassembles the instruction at `addr`, returning its text and length in bytes.
+    pub fn disassemble_at(&self, addr: u16) -> (String, u16) {
+        disassembler::disassemble_at(&self.ram, addr, &self.symbols)
+    }
+
+    /// Disassembles the instructions from `start` up to `end`, with their addresses.
+    pub fn disassemble(&self, start: u16, end: u16) -> Vec<(u16, String)> {
+        disassembler::disassemble(&self.ram, start, end, &self.symbols)
+    }
+
+    /// Renders the memory from `start` up to `end` as an annotated dump, see
+    /// [`disassembler::disassemble_text`].
+    pub fn disassemble_range_text(&self, start: u16, end: u16) -> String {
+        disassembler::disassemble_text(&self.ram, start, end, &self.symbols)
+    }
+
+    /// Installs `device` in `slot` (0x1 to 0xf, slot 0 being the system device). Its port masks
+    /// are read once, here. A [`ConsoleDevice`] or a [`ScreenDevice`] is unboxed, so that the VM
+    /// calls it directly.
+    pub fn set_device(&mut self, slot: u8, device: Box<dyn Device>) {
+        self.port_masks[slot as usize] = (device.dei_mask(), device.deo_mask());
+        self.installed |= 1 << slot;
+        self.devices[slot as usize] = Slot::new(device);
+    }
+
+    pub fn device(&self, slot: u8) -> &dyn Device {
+        self.devices[slot as usize].get()
+    }
+
+    pub fn device_mut(&mut self, slot: u8) -> &mut dyn Device {
+        self.devices[slot as usize].get_mut()
+    }
+
+    /// The slot and name of the system device and of every installed device, e.g.
+    /// `(0x1, "console")`.
+    pub fn device_names(&self) -> Vec<(u8, &'static str)> {
+        let installed = (1..16u8).filter(|slot| self.installed >> slot & 1 == 1);
+        std::iter::once((0, Device::name(self)))
+            .chain(installed.map(|slot| (slot, self.device(slot).name())))
+            .collect()
+    }
+
+    #[inline(always)]
+    /// Reads a byte or a short from RAM. A short at 0xffff wraps around to 0x0000 for its low
+    /// byte.
+    pub fn peek(&mut self, addr: usize, mode: InstructionMode) -> ExecutionResult<u16> {
+        if mode.is_short() {
+            Ok((self.ram[addr] as u16) << 8 | self.ram[(addr + 1) & 0xffff] as u16)
+        } else {
+            Ok(self.ram[addr] as u16)
+        }
+    }
+
+    #[inline(always)]
+    fn get_stack(&mut self, mode: InstructionMode) -> &mut Stack {
+        if mode.is_return() {
+            &mut self.rst
+        } else {
+            &mut self.wst
+        }
+    }
+
+    #[inline(always)]
+    pub fn kpop8(&mut self, mode: InstructionMode) -> ExecutionResult<u16> {
+        let s = self.get_stack(mode);
+        if s.kptr == 0 {
+            return Err(UxnError::StackUnderflow);
+        }
+        s.kptr -= 1;
+        Ok(s.data[s.kptr] as u16)
+    }
+
+    #[inline(always)]
+    pub fn kpop16(&mut self, mode: InstructionMode) -> ExecutionResult<u16> {
+        let s = self.get_stack(mode);
+        if s.kptr <= 1 {
+            return Err(UxnError::StackUnderflow);
+        }
+        s.kptr -= 2;
+        Ok(read_short_at(&s.data, s.kptr))
+    }
+
+    #[inline(always)]
+    pub fn pop8(&mut self, mode: InstructionMode) -> ExecutionResult<u16> {
+        let s = self.get_stack(mode);
+        if s.ptr == 0 {
+            return Err(UxnError::StackUnderflow);
+        }
+        s.ptr -= 1;
+        let value = s.data[s.ptr];
+        Ok(value as u16)
+    }
+
+    #[inline(always)]
+    pub fn pop16(&mut self, mode: InstructionMode) -> ExecutionResult<u16> {
+        let s = self.get_stack(mode);
+        if s.ptr <= 1 {
+            return Err(UxnError::StackUnderflow);
+        }
+        s.ptr -= 2;
+        Ok(read_short_at(&s.data, s.ptr))
+    }
+
+    #[inline(always)]
+    pub fn pop(&mut self, mode: InstructionMode) -> ExecutionResult<u16> {
+        if mode.is_keep() {
+            if mode.is_short() {
+                self.kpop16(mode)
+            } else {
+                self.kpop8(mode)
+            }
+        } else {
+            if mode.is_short() {
+                self.pop16(mode)
+            } else {
+                self.pop8(mode)
+            }
+        }
+    }
+
+    #[inline(always)]
+    pub fn push8(&mut self, v: u16, mode: InstructionMode) -> ExecutionResult<()> {
+        let s = self.get_stack(mode);
+        if s.ptr + 1 >= s.data.len() {
+            return Err(UxnError::StackOverflow {
+                stack: StackId::of(mode),
+                ptr: s.ptr,
+            });
+        }
+        s.data[s.ptr] = v as u8;
+        s.ptr += 1;
+        Ok(())
+    }
+    #[inline(always)]
     pub fn push16(&mut self, v: u16, mode: InstructionMode) -> ExecutionResult<()> {
-        let mut s = self.get_stack(mode);
-        if s.ptr >= 254 {
-            return Err("Stack overflow");
+        let s = self.get_stack(mode);
+        if s.ptr + 2 >= s.data.len() {
+            return Err(UxnError::StackOverflow {
+                stack: StackId::of(mode),
+                ptr: s.ptr,
+            });
         }
-        s.data[s.ptr as usize] = (v >> 8) as u8;
-        s.data[s.ptr as usize] = (v & 0xff) as u8;
+        write_short_at(&mut s.data, s.ptr, v);
         s.ptr += 2;
         Ok(())
     }
     #[inline(always)]
     pub fn push(&mut self, v: u16, mode: InstructionMode) -> ExecutionResult<()> {
-        if mode.contains(InstructionMode::Short) {
+        if mode.is_short() {
             self.push16(v, mode)
         } else {
             self.push8(v, mode)
         }
     }
 
+    /// Pushes the result of a comparison: a single byte, 1 or 0, whatever the width of the
+    /// operands. Only the return flag of `mode` matters, it selects the stack.
+    #[inline(always)]
+    fn push_flag(&mut self, flag: bool, mode: InstructionMode) -> ExecutionResult<()> {
+        self.push8(flag as u16, mode & InstructionMode::Return)
+    }
+
+    /// Pushes `data` onto the working stack, first byte first. Nothing is pushed if the whole
+    /// buffer doesn't fit.
+    pub fn push_bytes(&mut self, data: &[u8]) -> ExecutionResult<()> {
+        let s = &mut self.wst;
+        if s.ptr + data.len() >= s.data.len() {
+            return Err(UxnError::StackOverflow {
+                stack: StackId::Working,
+                ptr: s.ptr,
+            });
+        }
+        s.data[s.ptr..s.ptr + data.len()].copy_from_slice(data);
+        s.ptr += data.len();
+        Ok(())
+    }
+
+    /// Pops `n` bytes off the working stack, in the order they are popped (top of stack first).
+    /// Nothing is popped if the stack holds fewer than `n` bytes.
+    pub fn pop_bytes(&mut self, n: usize) -> ExecutionResult<Vec<u8>> {
+        let s = &mut self.wst;
+        if n > s.ptr {
+            return Err(UxnError::StackUnderflow);
+        }
+        s.ptr -= n;
+        Ok(s.data[s.ptr..s.ptr + n].iter().rev().copied().collect())
+    }
+
+    /// Sets what arithmetic instructions do on overflow. The default wraps, like uxn.
+    pub fn set_arithmetic_mode(&mut self, arithmetic: ArithmeticMode) {
+        self.arithmetic = arithmetic;
+    }
+
+    /// Runs `JCI`, `JMI` or `JSI`, whose operand is the short following them, an offset from
+    /// the address after it.
+    fn immediate_jump(&mut self, instr: u8, hooks: &mut dyn EvalHooks) -> ExecutionResult<()> {
+        let offset = self.peek(self.pc as usize, InstructionMode::Short)?;
+        self.pc = self.pc.wrapping_add(2);
+        let target = self.pc.wrapping_add(offset);
+        match instr {
+            JCI => {
+                if self.pop8(InstructionMode::None)? != 0 {
+                    self.pc = target;
+                }
+            }
+            JMI => self.pc = target,
+            _ => {
+                self.push16(self.pc, InstructionMode::Return)?;
+                self.pc = target;
+                hooks.on_call(self.pc);
+            }
+        }
+        Ok(())
+    }
+
+    /// Makes [`Uxn::run_to_breakpoint`] stop before running the instruction at `addr`.
+    pub fn set_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
+    }
+
+    pub fn clear_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.remove(&addr);
+    }
+
+    /// Runs from `start` like [`Uxn::eval_from`], but stops with [`EvalOutcome::Breakpoint`]
+    /// before an instruction at a breakpoint. The instruction at `start` always runs, so that
+    /// calling it again with [`Uxn::pc`] continues past the breakpoint.
+    pub fn run_to_breakpoint(&mut self, start: u16) -> Result<EvalOutcome, Fault> {
+        self.pc = start;
+        if self.is_halted {
+            return Ok(EvalOutcome::Halted);
+        }
+        loop {
+            match self.step() {
+                EvalOutcome::Stepped if self.breakpoints.contains(&self.pc) => {
+                    return Ok(EvalOutcome::Breakpoint(self.pc))
+                }
+                EvalOutcome::Stepped => {}
+                EvalOutcome::Error(fault) => return Err(fault),
+                outcome => return Ok(outcome),
+            }
+        }
+    }
+
+    /// Starts recording the address of every instruction run, for tests asserting a program's
+    /// control flow, or stops and drops the recording.
+    pub fn set_pc_trace(&mut self, enabled: bool) {
+        self.pc_trace = enabled.then(Vec::new);
+    }
+
+    /// The addresses recorded since [`Uxn::set_pc_trace`], in order. Empty when not tracing.
+    pub fn pc_trace(&self) -> &[u16] {
+        self.pc_trace.as_deref().unwrap_or(&[])
+    }
+
+    /// Faults with [`UxnError::ExecZeroPage`] instead of running code below 0x0100, to catch
+    /// wild jumps into the zero page. Off by default, uxn executes whatever the PC points to.
+    pub fn set_exec_guard(&mut self, on: bool) {
+        self.exec_guard = on;
+    }
+
+    /// Faults with [`UxnError::ExecUninitialized`] instead of running a byte nothing wrote since
+    /// boot: neither a loaded ROM nor a store. Off by default, uxn runs the zeros of unwritten
+    /// memory as `BRK`s, which hides jumps to the wrong place.
+    /// Devices writing RAM through [`Ram::set`] in [`Device::deo_ram`] count as stores.
+    pub fn set_entry_guard(&mut self, on: bool) {
+        self.entry_guard = on;
+    }
+
+    /// Narrows the result of an arithmetic instruction to the operand width, or fails if it
+    /// doesn't fit and overflows are checked.
+    fn arithmetic_result(&self, value: i64, mode: InstructionMode) -> ExecutionResult<u16> {
+        let max = if mode.is_short() { 0xffff } else { 0xff };
+        if self.arithmetic == ArithmeticMode::Checked && !(0..=max).contains(&value) {
+            return Err(UxnError::ArithmeticOverflow);
+        }
+        Ok((value & max) as u16)
+    }
+
+    /// Jumps to `addr`: absolutely in short mode, or by a signed byte offset from the PC.
+    /// The PC is already past the jumping instruction, so a relative `#00 JMP` is a no-op and
+    /// `#ff JMP` jumps back onto the `JMP` itself.
     #[inline(always)]
     pub fn warp(&mut self, addr: u16, mode: InstructionMode) -> ExecutionResult<()> {
-        if mode.contains(InstructionMode::Short) {
+        if mode.is_short() {
             self.pc = addr;
         } else {
-            self.pc += addr;
+            self.pc = self.pc.wrapping_add(addr as u8 as i8 as u16);
         }
         Ok(())
     }
 
+    /// Reads device memory at `addr`, asking the device for ports in its [`Device::dei_mask`].
+    fn device_in(&mut self, addr: u8) -> ExecutionResult<u8> {
+        match self.device_dei(addr >> 4, addr & 0x0f)? {
+            Some(value) => Ok(value),
+            None => Ok(self.dev[addr as usize]),
+        }
+    }
+
+    /// Writes `value` to device memory at `addr`, then notifies the device if the port is in
+    /// its [`Device::deo_mask`].
+    fn device_out(&mut self, addr: u8, value: u8) -> ExecutionResult<()> {
+        self.dev[addr as usize] = value;
+        self.device_deo(addr >> 4, addr & 0x0f, value)
+    }
+
+    /// A `DEI` of the byte at `addr`, which the hooks may answer instead of the device.
+    fn device_read(&mut self, addr: u8, hooks: &mut dyn EvalHooks) -> ExecutionResult<u8> {
+        match hooks.on_dei(addr >> 4, addr & 0x0f) {
+            Some(value) => Ok(value),
+            None => self.device_in(addr),
+        }
+    }
+
+    /// A `DEO` of the byte at `addr`, seen by the hooks before the device.
+    fn device_write(
+        &mut self,
+        addr: u8,
+        value: u8,
+        hooks: &mut dyn EvalHooks,
+    ) -> ExecutionResult<()> {
+        hooks.on_deo(addr >> 4, addr & 0x0f, value);
+        self.device_out(addr, value)
+    }
+
+    /// Writes the 16 ports of the device in `slot` straight into device memory, without calling
+    /// the device. For test harnesses that need a device to start in a given state; reads of
+    /// ports the device handles itself still go to the device.
+    pub fn init_device_memory(&mut self, slot: u8, bytes: &[u8; 16]) {
+        let start = (slot & 0x0f) as usize * 0x10;
+        self.dev[start..start + 0x10].copy_from_slice(bytes);
+    }
+
+    /// Reads `port` of the device in `slot`, `None` when the port is plain device memory.
+    #[inline(always)]
+    fn device_dei(&mut self, slot: u8, port: u8) -> ExecutionResult<Option<u8>> {
+        let slot = slot & 0x0f;
+        if slot == 0 {
+            // system device
+            return match self.dei_mask() >> port & 1 {
+                1 => self.dei(port).map(Some),
+                _ => Ok(None),
+            };
+        }
+        if self.installed >> slot & 1 == 0 {
+            return Err(UxnError::UnknownDevice(slot));
+        }
+        if self.port_masks[slot as usize].0 >> port & 1 == 0 {
+            return Ok(None);
+        }
+        let device = &self.devices[slot as usize];
+        let result = match device {
+            Slot::Console(console) => console.dei(port),
+            Slot::Screen(screen) => screen.dei(port),
+            Slot::Other(device) => device.dei(port),
+        };
+        result
+            .map(Some)
+            .map_err(|e| e.in_device(device.get().name()))
+    }
+
+    /// Notifies the device in `slot` of a write to `port`, if it is in its mask.
+    #[inline(always)]
+    fn device_deo(&mut self, slot: u8, port: u8, value: u8) -> ExecutionResult<()> {
+        let slot = slot & 0x0f;
+        if slot == 0 {
+            // system device
+            return match self.deo_mask() >> port & 1 {
+                1 => self.deo(port, value),
+                _ => Ok(()),
+            };
+        }
+        if self.installed >> slot & 1 == 0 {
+            return Err(UxnError::UnknownDevice(slot));
+        }
+        if self.port_masks[slot as usize].1 >> port & 1 == 0 {
+            return Ok(());
+        }
+        let mut ram = Ram {
+            bytes: &mut self.ram,
+            written: &mut self.written,
+        };
+        let result = match &mut self.devices[slot as usize] {
+            Slot::Console(console) => console.deo_ram(port, value, &mut ram),
+            Slot::Screen(screen) => screen.deo_ram(port, value, &mut ram),
+            Slot::Other(device) => device.deo_ram(port, value, &mut ram),
+        };
+        result.map_err(|e| e.in_device(self.device(slot).name()))
+    }
+
     #[inline(always)]
     pub fn poke(&mut self, addr: usize, value: u16, mode: InstructionMode) -> ExecutionResult<()> {
-        if mode.contains(InstructionMode::Short) {
+        if mode.is_short() {
             self.ram[addr] = (value >> 8) as u8;
-            self.ram[addr + 1] = (value & 0xff) as u8;
+            self.ram[(addr + 1) & 0xffff] = (value & 0xff) as u8;
+            self.mark_written((addr + 1) & 0xffff);
         } else {
             self.ram[addr] = value as u8;
         }
+        self.mark_written(addr);
         Ok(())
     }
 
-    pub fn eval(&mut self, start_addr: InstructionPointer) -> Result<(), &str> {
+    /// Runs the vector at `start_addr` until a `BRK` or a halt. Like in uxn, a vector of 0x0000
+    /// means no vector, and nothing runs; neither does anything once the VM halted. Use
+    /// [`Uxn::eval_from`] to run code at 0x0000, or to tell how the run ended.
+    pub fn eval(&mut self, start_addr: InstructionPointer) -> Result<(), Fault> {
+        self.eval_with(start_addr, &mut ())
+    }
+
+    /// Runs from any address, including 0x0000, until a `BRK` or a halt, returning which one
+    /// ended the run. A halted VM doesn't run and returns [`EvalOutcome::Halted`] right away.
+    pub fn eval_from(&mut self, addr: InstructionPointer) -> Result<EvalOutcome, Fault> {
+        self.pc = addr;
+        if self.is_halted {
+            return Ok(EvalOutcome::Halted);
+        }
+        self.run_with(&mut ())
+    }
+
+    /// Continues from the current PC until a `BRK` or a halt, e.g. after a breakpoint or a
+    /// budget ran out. Unlike [`Uxn::eval_from`], the PC is left as it is.
+    pub fn resume(&mut self) -> Result<EvalOutcome, Fault> {
+        if self.is_halted {
+            return Ok(EvalOutcome::Halted);
+        }
+        self.run_with(&mut ())
+    }
+
+    /// Like [`Uxn::eval`], calling `hooks` along the way.
+    pub fn eval_with(
+        &mut self,
+        start_addr: InstructionPointer,
+        hooks: &mut dyn EvalHooks,
+    ) -> Result<(), Fault> {
         self.pc = start_addr;
 
         if self.pc == 0x0 || self.is_halted {
             return Ok(());
         }
+        self.run_with(hooks).map(|_| ())
+    }
 
+    /// Steps until a `BRK` or a halt, returning which one it was.
+    fn run_with(&mut self, hooks: &mut dyn EvalHooks) -> Result<EvalOutcome, Fault> {
         loop {
-            let instr = self.ram[self.pc as usize];
-            let opcode = (instr & 0x1f).into();
-
-            self.pc += 1;
-            if instr == 0x00 {
-                break;
+            match self.step_with(hooks) {
+                EvalOutcome::Stepped => {}
+                EvalOutcome::Error(fault) => return Err(fault),
+                outcome => return Ok(outcome),
             }
+        }
+    }
 
-            let mode: InstructionMode = instr.into();
-            let is_keep = mode.contains(InstructionMode::Keep);
+    /// Like [`Uxn::eval`], with closures observing device writes and intercepting device reads,
+    /// see [`EvalHooks::on_deo`] and [`EvalHooks::on_dei`].
+    pub fn eval_with_hooks(
+        &mut self,
+        start_addr: InstructionPointer,
+        on_deo: impl FnMut(u8, u8, u8),
+        on_dei: impl FnMut(u8, u8) -> Option<u8>,
+    ) -> Result<(), Fault> {
+        self.eval_with(start_addr, &mut DeviceHooks { on_deo, on_dei })
+    }
 
-            if is_keep {
-                self.wst.kptr = self.wst.ptr;
-                self.rst.kptr = self.rst.ptr;
-            }
+    /// Execute the single instruction at the program counter.
+    pub fn step(&mut self) -> EvalOutcome {
+        self.step_with(&mut ())
+    }
 
-            let res: Result<(), &str> = match opcode {
-                Opcode::LIT => self
-                    .peek(self.pc as usize, mode)
-                    .and_then(|a| {
-                        self.push(a, mode).and_then(|_| {
-                            self.pc += 1;
-                            if mode.contains(InstructionMode::Short) {
-                                self.pc += 1;
-                            }
-                            Ok(())
-                        })
-                    })
-                    .into(),
-                Opcode::INC => self.pop(mode).and_then(|a| self.push(a + 1, mode)).into(),
-                Opcode::POP => self.pop(mode).and_then(|_| Ok(())),
-                Opcode::NIP => self
-                    .pop(mode)
-                    .and_then(|a| self.pop(mode).and_then(|_| self.push(a, mode)))
-                    .into(),
-                Opcode::SWP => self
-                    .pop(mode)
-                    .and_then(|a| {
-                        self.pop(mode)
-                            .and_then(|b| self.push(a, mode).and_then(|_| self.push(b, mode)))
-                    })
-                    .into(),
-                Opcode::ROT => self
-                    .pop(mode)
-                    .and_then(|a| {
-                        self.pop(mode).and_then(|b| {
-                            self.pop(mode).and_then(|c| {
-                                self.push(b, mode)
-                                    .and_then(|_| self.push(a, mode))
-                                    .and_then(|_| self.push(c, mode))
-                            })
-                        })
-                    })
-                    .into(),
-                Opcode::DUP => self
-                    .pop(mode)
-                    .and_then(|a| self.push(a, mode).and_then(|_| self.push(a, mode)))
-                    .into(),
-                Opcode::OVR => self
-                    .pop(mode)
-                    .and_then(|a| {
-                        self.pop(mode).and_then(|b| {
-                            self.push(b, mode)
-                                .and_then(|_| self.push(a, mode).and_then(|_| self.push(b, mode)))
-                        })
-                    })
-                    .into(),
-                Opcode::EQU => self
-                    .pop(mode)
-                    .and_then(|a| {
-                        self.pop(mode)
-                            .and_then(|b| self.push8(if a == b { 1 } else { 0 }, mode))
-                    })
-                    .into(),
-                Opcode::NEQ => self
-                    .pop(mode)
-                    .and_then(|a| {
-                        self.pop(mode)
-                            .and_then(|b| self.push8(if a != b { 1 } else { 0 }, mode))
-                    })
-                    .into(),
-                Opcode::GTH => self
-                    .pop(mode)
-                    .and_then(|a| {
-                        self.pop(mode)
-                            .and_then(|b| self.push8(if b > a { 1 } else { 0 }, mode))
-                    })
-                    .into(),
-                Opcode::LTH => self
-                    .pop(mode)
-                    .and_then(|a| {
-                        self.pop(mode)
-                            .and_then(|b| self.push8(if b < a { 1 } else { 0 }, mode))
-                    })
-                    .into(),
-                Opcode::JMP => self.pop(mode).and_then(|a| self.warp(a, mode)).into(),
-                Opcode::JCN => self
-                    .pop(mode)
-                    .and_then(|a| {
-                        self.pop8(mode)
-                            .and_then(|b| if b != 0 { self.warp(a, mode) } else { Ok(()) })
-                    })
-                    .into(),
-                Opcode::JSR => self
-                    .pop(mode)
-                    .and_then(|a| {
-                        self.push8(
-                            self.pc,
-                            if mode.contains(InstructionMode::Return) {
-                                InstructionMode::None
-                            } else {
-                                InstructionMode::Return
-                            },
-                        )
-                        .and_then(|_| self.warp(a, mode))
-                    })
-                    .into(),
-                Opcode::STH => self
-                    .pop(mode)
-                    .and_then(|a| {
-                        self.push16(
-                            a,
-                            if mode.contains(InstructionMode::Return) {
-                                InstructionMode::None
-                            } else {
-                                InstructionMode::Return
-                            },
-                        )
-                    })
-                    .into(),
-                Opcode::LDZ => self
-                    .pop8(mode)
-                    .and_then(|a| self.peek(a as usize, mode).and_then(|b| self.push(b, mode)))
-                    .into(),
-                Opcode::STZ => self
-                    .pop8(mode)
-                    .and_then(|a| self.pop(mode).and_then(|b| self.poke(a as usize, b, mode)))
-                    .into(),
-                Opcode::LDR => self
-                    .pop8(mode)
-                    .and_then(|a| {
-                        self.peek((a + self.pc) as usize, mode)
-                            .and_then(|b| self.push(b, mode))
-                    })
-                    .into(),
-                Opcode::STR => self
-                    .pop8(mode)
-                    .and_then(|a| {
-                        self.pop(mode)
-                            .and_then(|b| self.poke((a + self.pc) as usize, b, mode))
+    /// Like [`Uxn::step`], calling `hooks` along the way.
+    pub fn step_with(&mut self, hooks: &mut dyn EvalHooks) -> EvalOutcome {
+        // a halted VM runs nothing more, however it is driven
+        if self.is_halted {
+            return EvalOutcome::Halted;
+        }
+        let pc = self.pc;
+        if let Some(trace) = &mut self.pc_trace {
+            trace.push(pc);
+        }
+        match self.execute(hooks) {
+            Ok(outcome) => outcome,
+            Err(error) => EvalOutcome::Error(Fault {
+                error,
+                pc,
+                opcode: self.ram[pc as usize],
+                wst_ptr: self.wst.ptr,
+                rst_ptr: self.rst.ptr,
+            }),
+        }
+    }
+
+    fn execute(&mut self, hooks: &mut dyn EvalHooks) -> ExecutionResult<EvalOutcome> {
+        if self.exec_guard && self.pc < 0x0100 {
+            return Err(UxnError::ExecZeroPage(self.pc));
+        }
+        if self.entry_guard && !self.is_written(self.pc) {
+            return Err(UxnError::ExecUninitialized(self.pc));
+        }
+        let instr = self.ram[self.pc as usize];
+        let (opcode, mode) = decode_byte(instr);
+
+        self.pc = self.pc.wrapping_add(1);
+        if instr == 0x00 {
+            return Ok(EvalOutcome::Break);
+        }
+        if matches!(instr, JCI | JMI | JSI) {
+            self.immediate_jump(instr, hooks)?;
+            return Ok(EvalOutcome::Stepped);
+        }
+
+        let is_keep = mode.is_keep();
+
+        if is_keep {
+            self.wst.kptr = self.wst.ptr;
+            self.rst.kptr = self.rst.ptr;
+        }
+
+        let res: ExecutionResult<()> = match opcode {
+            Opcode::LIT => self.peek(self.pc as usize, mode).and_then(|a| {
+                self.push(a, mode).map(|_| {
+                    let len = if mode.is_short() { 2 } else { 1 };
+                    self.pc = self.pc.wrapping_add(len);
+                })
+            }),
+            Opcode::INC => self.pop(mode).and_then(|a| {
+                let c = self.arithmetic_result(a as i64 + 1, mode)?;
+                self.push(c, mode)
+            }),
+            Opcode::POP => self.pop(mode).map(|_| ()),
+            Opcode::NIP => self
+                .pop(mode)
+                .and_then(|a| self.pop(mode).and_then(|_| self.push(a, mode))),
+            Opcode::SWP => self.pop(mode).and_then(|a| {
+                self.pop(mode)
+                    .and_then(|b| self.push(a, mode).and_then(|_| self.push(b, mode)))
+            }),
+            Opcode::ROT => self.pop(mode).and_then(|a| {
+                self.pop(mode).and_then(|b| {
+                    self.pop(mode).and_then(|c| {
+                        self.push(b, mode)
+                            .and_then(|_| self.push(a, mode))
+                            .and_then(|_| self.push(c, mode))
                     })
-                    .into(),
-                Opcode::LDA => self
-                    .pop16(mode)
-                    .and_then(|a| self.peek(a as usize, mode).and_then(|b| self.push(b, mode)))
-                    .into(),
-                Opcode::STA => self
-                    .pop16(mode)
-                    .and_then(|a| self.pop(mode).and_then(|b| self.poke(a as usize, b, mode)))
-                    .into(),
-                Opcode::DEI => {
-                    self.pop8(mode)
-                        .and_then(|a| {
-                            {
-                                let device = ((a >> 4) & 0x0f) as usize;
-                                let port = (a & 0x0F) as u8;
-                                if device == 0 {
-                                    // system device
-                                    self.dei(port)
-                                } else {
-                                    self.devices[device].dei(port)
-                                }
-                            }
-                            .and_then(|b| self.push(b as u16, mode))
-                        })
-                        .into()
+                })
+            }),
+            Opcode::DUP => self
+                .pop(mode)
+                .and_then(|a| self.push(a, mode).and_then(|_| self.push(a, mode))),
+            Opcode::OVR => self.pop(mode).and_then(|a| {
+                self.pop(mode).and_then(|b| {
+                    self.push(b, mode)
+                        .and_then(|_| self.push(a, mode).and_then(|_| self.push(b, mode)))
+                })
+            }),
+            // EQU a b -- a==b
+            // operands follow the short flag, the result is always a single byte
+            Opcode::EQU => self
+                .pop(mode)
+                .and_then(|a| self.pop(mode).and_then(|b| self.push_flag(b == a, mode))),
+            // NEQ a b -- a!=b
+            Opcode::NEQ => self
+                .pop(mode)
+                .and_then(|a| self.pop(mode).and_then(|b| self.push_flag(b != a, mode))),
+            // GTH a b -- a>b
+            Opcode::GTH => self
+                .pop(mode)
+                .and_then(|a| self.pop(mode).and_then(|b| self.push_flag(b > a, mode))),
+            // LTH a b -- a<b
+            Opcode::LTH => self
+                .pop(mode)
+                .and_then(|a| self.pop(mode).and_then(|b| self.push_flag(b < a, mode))),
+            Opcode::JMP => self.pop(mode).and_then(|a| {
+                self.warp(a, mode)?;
+                // a `JMP2r` jumps to the return address pushed by a `JSR`
+                if mode.is_short() && mode.is_return() {
+                    hooks.on_return(self.pc);
                 }
-                Opcode::DEO => {
-                    self.pop8(mode)
-                        .and_then(|a| {
-                            self.pop(mode).and_then(|value| {
-                                let device = ((a >> 4) & 0x0f) as usize;
-                                let port = (a & 0x0F) as u8;
-                                if device == 0 {
-                                    // system device
-                                    self.deo(port, a as u8)
-                                } else {
-                                    self.devices[device].deo(port, value as u8)
-                                }
-                            })
-                        })
-                        .into()
+                Ok(())
+            }),
+            Opcode::JCN => self.pop(mode).and_then(|a| {
+                self.pop(mode.as_byte())
+                    .and_then(|b| if b != 0 { self.warp(a, mode) } else { Ok(()) })
+            }),
+            // the return address is always a short, pushed to the opposite stack,
+            // so that `JMP2r` returns from a JSR and `JMP2` from a JSRr
+            Opcode::JSR => self.pop(mode).and_then(|a| {
+                self.push16(self.pc, mode.other_stack())?;
+                self.warp(a, mode)?;
+                hooks.on_call(self.pc);
+                Ok(())
+            }),
+            Opcode::STH => self
+                .pop(mode)
+                .and_then(|a| self.push(a, mode.other_stack())),
+            // the address is a zero-page byte, but like the reference implementation a short
+            // at 0xff spans 0x00ff-0x0100 rather than wrapping around to 0x0000
+            Opcode::LDZ => self
+                .pop(mode.as_byte())
+                .and_then(|a| self.peek(a as usize, mode).and_then(|b| self.push(b, mode))),
+            Opcode::STZ => self
+                .pop(mode.as_byte())
+                .and_then(|a| self.pop(mode).and_then(|b| self.poke(a as usize, b, mode))),
+            // the address is a signed byte offset from the PC, which is already past the
+            // instruction, wrapping around memory
+            Opcode::LDR => self.pop(mode.as_byte()).and_then(|a| {
+                let addr = self.pc.wrapping_add(a as u8 as i8 as u16);
+                self.peek(addr as usize, mode)
+                    .and_then(|b| self.push(b, mode))
+            }),
+            Opcode::STR => self.pop(mode.as_byte()).and_then(|a| {
+                let addr = self.pc.wrapping_add(a as u8 as i8 as u16);
+                self.pop(mode)
+                    .and_then(|b| self.poke(addr as usize, b, mode))
+            }),
+            Opcode::LDA => self
+                .pop(mode.as_short())
+                .and_then(|a| self.peek(a as usize, mode).and_then(|b| self.push(b, mode))),
+            Opcode::STA => self
+                .pop(mode.as_short())
+                .and_then(|a| self.pop(mode).and_then(|b| self.poke(a as usize, b, mode))),
+            // a short is read from or written to the port and the one after it, high byte first
+            Opcode::DEI => self.pop(mode.as_byte()).and_then(|a| {
+                let addr = a as u8;
+                if mode.is_short() {
+                    let high = self.device_read(addr, hooks)?;
+                    let low = self.device_read(addr.wrapping_add(1), hooks)?;
+                    self.push16(u16::from_be_bytes([high, low]), mode)
+                } else {
+                    let value = self.device_read(addr, hooks)?;
+                    self.push8(value as u16, mode)
                 }
-                Opcode::ADD => self
-                    .pop(mode)
-                    .and_then(|a| self.pop(mode).and_then(|b| self.push(a + b, mode)))
-                    .into(),
-                Opcode::SUB => self
-                    .pop(mode)
-                    .and_then(|a| self.pop(mode).and_then(|b| self.push(b - a, mode)))
-                    .into(),
-                Opcode::MUL => self
-                    .pop(mode)
-                    .and_then(|a| self.pop(mode).and_then(|b| self.push(a * b, mode)))
-                    .into(),
-                Opcode::DIV => self.pop(mode).and_then(|a| {
-                    self.pop(mode).and_then(|b| {
-                        if a == 0 {
-                            Err("Division by zero")
-                        } else {
-                            self.push(b / a, mode)
-                        }
-                    })
-                }),
-                Opcode::AND => self
-                    .pop(mode)
-                    .and_then(|a| self.pop(mode).and_then(|b| self.push(a & b, mode)))
-                    .into(),
-                Opcode::ORA => self
-                    .pop(mode)
-                    .and_then(|a| self.pop(mode).and_then(|b| self.push(a | b, mode)))
-                    .into(),
-                Opcode::EOR => self
-                    .pop(mode)
-                    .and_then(|a| self.pop(mode).and_then(|b| self.push(a ^ b, mode)))
-                    .into(),
-                Opcode::SFT => self
-                    .pop(mode)
-                    .and_then(|a| {
-                        self.pop8(mode)
-                            .and_then(|b| self.push(a << ((b & 0xF0) >> 4) >> (b & 0x0F), mode))
-                    })
-                    .into(),
-            };
-            if res.is_err() {
-                return res;
+            }),
+            Opcode::DEO => self.pop(mode.as_byte()).and_then(|a| {
+                let addr = a as u8;
+                self.pop(mode).and_then(|value| {
+                    if mode.is_short() {
+                        let [high, low] = value.to_be_bytes();
+                        self.device_write(addr, high, hooks)?;
+                        self.device_write(addr.wrapping_add(1), low, hooks)
+                    } else {
+                        self.device_write(addr, value as u8, hooks)
+                    }
+                })
+            }),
+            Opcode::ADD => self.pop(mode).and_then(|a| {
+                self.pop(mode).and_then(|b| {
+                    let c = self.arithmetic_result(b as i64 + a as i64, mode)?;
+                    self.push(c, mode)
+                })
+            }),
+            Opcode::SUB => self.pop(mode).and_then(|a| {
+                self.pop(mode).and_then(|b| {
+                    let c = self.arithmetic_result(b as i64 - a as i64, mode)?;
+                    self.push(c, mode)
+                })
+            }),
+            Opcode::MUL => self.pop(mode).and_then(|a| {
+                self.pop(mode).and_then(|b| {
+                    let c = self.arithmetic_result(b as i64 * a as i64, mode)?;
+                    self.push(c, mode)
+                })
+            }),
+            Opcode::DIV => self.pop(mode).and_then(|a| {
+                self.pop(mode).and_then(|b| match b.checked_div(a) {
+                    Some(v) => self.push(v, mode),
+                    None => Err(UxnError::DivisionByZero),
+                })
+            }),
+            Opcode::AND => self
+                .pop(mode)
+                .and_then(|a| self.pop(mode).and_then(|b| self.push(a & b, mode))),
+            Opcode::ORA => self
+                .pop(mode)
+                .and_then(|a| self.pop(mode).and_then(|b| self.push(a | b, mode))),
+            Opcode::EOR => self
+                .pop(mode)
+                .and_then(|a| self.pop(mode).and_then(|b| self.push(a ^ b, mode))),
+            // SFT a shift -- c
+            // the shift is always a byte: shift right by its low nibble, then left by its
+            // high nibble. Bits shifted out of the value's width are lost.
+            Opcode::SFT => self.pop(mode.as_byte()).and_then(|shift| {
+                self.pop(mode).and_then(|a| {
+                    let shifted = a
+                        .wrapping_shr(shift as u32 & 0x0f)
+                        .wrapping_shl(shift as u32 >> 4);
+                    self.push(shifted, mode)
+                })
+            }),
+        };
+        res?;
+
+        if self.is_halted {
+            Ok(EvalOutcome::Halted)
+        } else {
+            Ok(EvalOutcome::Stepped)
+        }
+    }
+
+    /// Like [`Uxn::step`], but a `JSR` or `JSI` runs until the subroutine returns, stopping at
+    /// the instruction after the call. Faults are an [`EvalOutcome::Error`] like for `step`,
+    /// so that a debugger handles both the same way.
+    pub fn step_over(&mut self) -> EvalOutcome {
+        let instr = self.ram[self.pc as usize];
+        // the return address goes on the other stack, or the return stack for JSI, and
+        // returning pops it off again
+        let stack = match decode_byte(instr) {
+            _ if instr == JSI => InstructionMode::Return,
+            (Opcode::JSR, mode) => mode.other_stack(),
+            _ => return self.step(),
+        };
+
+        let depth = self.get_stack(stack).ptr;
+        let mut outcome = self.step();
+        while outcome == EvalOutcome::Stepped && self.get_stack(stack).ptr > depth {
+            outcome = self.step();
+        }
+        outcome
+    }
+
+    /// Runs up to `budget` instructions from the current PC, returning why it stopped and how
+    /// many instructions ran, including the one that stopped it. `Stepped` means the budget ran
+    /// out and a later call continues where this one left off, so that several VMs can share a
+    /// thread in turns.
+    pub fn eval_budget(&mut self, budget: u64) -> (EvalOutcome, u64) {
+        if self.is_halted {
+            return (EvalOutcome::Halted, 0);
+        }
+        let mut used = 0;
+        while used < budget {
+            used += 1;
+            let outcome = self.step();
+            if outcome != EvalOutcome::Stepped {
+                return (outcome, used);
+            }
+        }
+        (EvalOutcome::Stepped, used)
+    }
+
+    /// Runs up to `max` instructions from `start`, returning why it stopped and a trace with a
+    /// line per instruction: its address, its disassembly and both stacks after it, e.g.
+    /// `0104  ADD          wst [03] rst []`.
+    pub fn trace_run(&mut self, start: u16, max: u64) -> (EvalOutcome, String) {
+        let hex = |stack: &Stack| {
+            let bytes: Vec<String> = stack.data[..stack.ptr]
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect();
+            bytes.join(" ")
+        };
+        let mut trace = String::new();
+        self.pc = start;
+        for _ in 0..max {
+            let pc = self.pc;
+            let (text, _) = self.disassemble_at(pc);
+            let outcome = self.step();
+            trace.push_str(&format!(
+                "{:04x}  {:<12} wst [{}] rst [{}]\n",
+                pc,
+                text,
+                hex(&self.wst),
+                hex(&self.rst)
+            ));
+            if outcome != EvalOutcome::Stepped {
+                return (outcome, trace);
             }
         }
+        (EvalOutcome::Stepped, trace)
+    }
 
-        Ok(())
+    /// The vector of the device in `slot`, the short at its first port.
+    pub fn vector(&self, slot: u8) -> u16 {
+        let addr = slot as usize * 0x10;
+        read_short_at(&self.dev, addr)
     }
 
-    pub fn halt(&mut self) {
-        self.wst.print();
-        self.rst.print();
+    /// Gives the console a byte of input, of `kind`, and runs its vector. The vector reads the
+    /// byte from the console's read port and its kind from the type port.
+    pub fn feed_console(&mut self, byte: u8, kind: ConsoleInputKind) -> Result<(), Fault> {
+        self.set_console_input(byte, kind);
+        self.eval(self.vector(devices::CONSOLE))
     }
 
-    pub fn print(&self) {}
+    fn set_console_input(&mut self, byte: u8, kind: ConsoleInputKind) {
+        let base = devices::CONSOLE as usize * 0x10;
+        self.dev[base + console::PORT_READ as usize] = byte;
+        self.dev[base + console::PORT_TYPE as usize] = kind as u8;
+    }
+
+    /// Queues the vector of the input device in `slot`, to be run by the next
+    /// [`Uxn::run_frame`].
+    pub fn queue_input(&mut self, slot: u8) {
+        self.input_vectors.push_back(slot);
+    }
+
+    /// Runs one frame: the queued input vectors, then the screen vector, then advances the
+    /// audio channels by `dt_samples`.
+    ///
+    /// Input vectors run in the order of [`devices::VECTOR_ORDER`], not the order they were
+    /// queued in, so that a frame runs the same whichever way a frontend polls its inputs.
+    pub fn run_frame(&mut self, dt_samples: usize) -> Result<FrameResult, Fault> {
+        let mut inputs: Vec<u8> = self.input_vectors.drain(..).collect();
+        inputs.sort_by_key(|slot| devices::vector_priority(*slot));
+        for slot in inputs {
+            self.eval(self.vector(slot))?;
+        }
+        self.eval(self.vector(devices::SCREEN))?;
+
+        let mut audio_samples = 0;
+        for slot in devices::AUDIO {
+            if let Some(audio) = self.device_mut(slot).downcast_mut::<AudioDevice>() {
+                audio_samples += audio.advance(dt_samples);
+            }
+        }
+        let screen_dirty = self
+            .device_mut(devices::SCREEN)
+            .downcast_mut::<ScreenDevice>()
+            .is_some_and(|screen| screen.take_dirty());
+
+        Ok(FrameResult {
+            halted: self.is_halted,
+            screen_dirty,
+            audio_samples,
+        })
+    }
+
+    /// Runs one frame like [`Uxn::run_frame`], advancing audio by the samples the clock counted
+    /// since the previous clocked frame.
+    pub fn run_clocked_frame(&mut self) -> Result<FrameResult, Fault> {
+        let samples = self.clock.elapsed_samples();
+        let dt_samples = samples.saturating_sub(self.clock_samples);
+        self.clock_samples = samples;
+        self.run_frame(dt_samples)
+    }
+
+    /// A hash of the state of the VM: RAM, the used part of both stacks, the PC, device memory
+    /// and whether it halted. It is stable across runs and platforms, so that runs of the same
+    /// ROM on different builds can be compared.
+    pub fn state_hash(&self) -> u64 {
+        // 64-bit FNV-1a
+        let mut hash: u64 = 0xcbf29ce484222325;
+        let mut write = |bytes: &[u8]| {
+            for byte in bytes {
+                hash ^= *byte as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        };
+        write(&self.ram);
+        for stack in [&self.wst, &self.rst] {
+            write(&(stack.ptr as u64).to_be_bytes());
+            write(&stack.data[..stack.ptr]);
+        }
+        write(&self.pc.to_be_bytes());
+        write(&self.dev);
+        write(&[self.is_halted as u8]);
+        hash
+    }
+
+    /// Whether the program halted by writing to the system state port.
+    pub fn is_halted(&self) -> bool {
+        self.is_halted
+    }
+
+    /// The exit code the program halted with, the low 7 bits of the value written to the system
+    /// state port: `#80` halts with 0, `#01` with 1. `None` while it hasn't halted.
+    pub fn exit_code(&self) -> Option<u8> {
+        self.is_halted.then_some(self.exit_code)
+    }
+
+    /// Address of the next instruction to execute.
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    /// Moves the program counter, e.g. to [`Uxn::step`] through code that isn't a vector.
+    pub fn set_pc(&mut self, addr: u16) {
+        self.pc = addr;
+    }
+
+    /// Halts the VM from the host, like a ROM writing 0x80 to the system state port: the
+    /// exit code is 0 and the devices get their [`Device::on_halt`].
+    pub fn halt(&mut self) -> ExecutionResult<()> {
+        self.is_halted = true;
+        self.exit_code = 0;
+        self.halt_devices()
+    }
+
+    fn halt_devices(&mut self) -> ExecutionResult<()> {
+        for slot in 1..16 {
+            if self.installed >> slot & 1 == 1 {
+                self.devices[slot].get_mut().on_halt()?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn print(&self) {}
+}
+
+#[test]
+fn device_downcast() {
+    #[derive(Default)]
+    struct RecordingDevice {
+        writes: Vec<(PortAddress, u8)>,
+    }
+
+    impl Device for RecordingDevice {
+        fn dei(&self, _port: PortAddress) -> ExecutionResult<u8> {
+            Ok(0)
+        }
+        fn deo(&mut self, port: PortAddress, value: u8) -> ExecutionResult<()> {
+            self.writes.push((port, value));
+            Ok(())
+        }
+    }
+
+    let mut uxn = Uxn::new();
+    uxn.boot();
+    uxn.set_device(1, Box::<RecordingDevice>::default());
+    let rom = crate::assembler::assemble("|0100 #41 #18 DEO #42 #19 DEO BRK").unwrap();
+    uxn.load_program(&rom.rom, 0x100);
+    uxn.eval(0x100).unwrap();
+
+    let device = uxn.device_mut(1).downcast_mut::<RecordingDevice>().unwrap();
+    assert_eq!(device.writes, vec![(0x08, 0x41), (0x09, 0x42)]);
+    assert!(uxn.device(2).downcast_ref::<RecordingDevice>().is_none());
+}
+
+#[test]
+fn device_memory_registers() {
+    // only the write port has side effects, the other ports are plain registers
+    #[derive(Default)]
+    struct WriteOnlyDevice {
+        written: Vec<u8>,
+    }
+
+    impl Device for WriteOnlyDevice {
+        fn dei(&self, _port: PortAddress) -> ExecutionResult<u8> {
+            Err(UxnError::Device {
+                name: self.name(),
+                reason: "unhandled port",
+            })
+        }
+        fn deo(&mut self, _port: PortAddress, value: u8) -> ExecutionResult<()> {
+            self.written.push(value);
+            Ok(())
+        }
+        fn dei_mask(&self) -> u16 {
+            0x0000
+        }
+        fn deo_mask(&self) -> u16 {
+            0x0100
+        }
+    }
+
+    let mut uxn = Uxn::new();
+    uxn.boot();
+    uxn.set_device(3, Box::<WriteOnlyDevice>::default());
+    let rom = crate::assembler::assemble(
+        "|0100 #2a #3a DEO #3a DEI #00 STZ #41 #38 DEO #38 DEI #01 STZ #99 #08 DEO #08 DEI #02 STZ BRK",
+    )
+    .unwrap();
+    uxn.load_program(&rom.rom, 0x100);
+    uxn.eval(0x100).unwrap();
+
+    assert_eq!(uxn.ram[0x00], 0x2a);
+    assert_eq!(uxn.ram[0x01], 0x41);
+    // system palette ports are registers as well
+    assert_eq!(uxn.ram[0x02], 0x99);
+    let device = uxn.device(3).downcast_ref::<WriteOnlyDevice>().unwrap();
+    assert_eq!(device.written, vec![0x41]);
+}
+
+#[cfg(test)]
+fn run(src: &str) -> Uxn {
+    let mut uxn = Uxn::new();
+    uxn.boot();
+    uxn.load_program(&crate::assembler::assemble(src).unwrap().rom, 0x100);
+    uxn.eval(0x100).unwrap();
+    uxn
+}
+
+#[cfg(test)]
+fn stack_contents(stack: &Stack) -> &[u8] {
+    &stack.data[..stack.ptr]
+}
+
+#[test]
+fn shorts_round_trip_through_the_stacks() {
+    // xorshift, seeded so that failures reproduce
+    let mut state = 0x2545_f491u32;
+    let mut random = move || {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state
+    };
+    let short = InstructionMode::Short;
+    for _ in 0..100 {
+        let mut uxn = Uxn::new();
+        let mut pushed = vec![];
+        for _ in 0..random() % 120 {
+            let (v, mode) = match random() % 3 {
+                0 => (random() as u8 as u16, InstructionMode::None),
+                1 => (random() as u16, short),
+                _ => (random() as u16, short | InstructionMode::Return),
+            };
+            uxn.push(v, mode).unwrap();
+            pushed.push((v, mode));
+        }
+        for (v, mode) in pushed.into_iter().rev() {
+            if mode == short {
+                // what an instruction in keep mode sees
+                uxn.wst.kptr = uxn.wst.ptr;
+                assert_eq!(uxn.kpop16(short | InstructionMode::Keep), Ok(v));
+            }
+            assert_eq!(uxn.pop(mode), Ok(v), "popping {:?}", mode);
+        }
+    }
+}
+
+#[test]
+fn keep_mode_pops() {
+    for (src, expected) in [
+        ("#01 #02 ADDk", &[0x01, 0x02, 0x03][..]),
+        ("#1234 #5678 ADD2k", &[0x12, 0x34, 0x56, 0x78, 0x68, 0xac]),
+        (
+            "#1234 #5678 #9abc ROT2k",
+            &[
+                0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0x56, 0x78, 0x9a, 0xbc, 0x12, 0x34,
+            ],
+        ),
+        // fixed-width operands are kept too: the condition, the port
+        ("#00 #02 JCNk", &[0x00, 0x02]),
+        ("#ab #00 DEOk", &[0xab, 0x00]),
+        (";data LDAk BRK @data 2a", &[0x01, 0x05, 0x2a]),
+        (";data LDA2k BRK @data 2a2b", &[0x01, 0x05, 0x2a, 0x2b]),
+    ] {
+        let mut uxn = Uxn::try_from(src).unwrap();
+        uxn.eval(0x100).unwrap();
+        assert_eq!(stack_contents(&uxn.wst), expected, "{}", src);
+    }
+
+    let mut uxn = Uxn::try_from("#12 ADD2k").unwrap();
+    let fault = uxn.eval(0x100).unwrap_err();
+    assert_eq!(fault.error, UxnError::StackUnderflow);
+}
+
+#[test]
+fn comparisons_in_return_mode() {
+    for (src, result) in [
+        ("LITr 12 LITr 12 EQUr", 0x01),
+        ("LITr 12 LITr 34 EQUr", 0x00),
+        ("LITr 12 LITr 34 NEQr", 0x01),
+        ("LITr 34 LITr 12 GTHr", 0x01),
+        ("LITr 12 LITr 34 GTHr", 0x00),
+        ("LITr 12 LITr 34 LTHr", 0x01),
+        ("LIT2r 1234 LIT2r 1234 EQU2r", 0x01),
+        ("LIT2r 1234 LIT2r 1235 EQU2r", 0x00),
+        ("LIT2r 1234 LIT2r 1235 NEQ2r", 0x01),
+        ("LIT2r 1235 LIT2r 1234 GTH2r", 0x01),
+        ("LIT2r 1234 LIT2r 1235 GTH2r", 0x00),
+        ("LIT2r 1234 LIT2r 1235 LTH2r", 0x01),
+    ] {
+        // the working stack holds a sentinel that must not be touched
+        let uxn = run(&format!("|0100 #aa {} BRK", src));
+        assert_eq!(stack_contents(&uxn.rst), [result], "{}", src);
+        assert_eq!(stack_contents(&uxn.wst), [0xaa], "{}", src);
+    }
+}
+
+#[test]
+fn memory_after_sta2() {
+    let mut uxn = run("|0100 #abcd #2000 STA2 BRK");
+    assert_eq!(uxn.memory()[0x2000..0x2002], [0xab, 0xcd]);
+    assert_eq!(uxn.memory()[0x2002], 0x00);
+
+    uxn.memory_mut()[0x2002] = 0xef;
+    assert_eq!(uxn.peek(0x2001, InstructionMode::Short), Ok(0xcdef));
+}
+
+#[test]
+fn zero_page_shorts() {
+    let uxn = run("|0100 #1234 #10 STZ2 #10 LDZ2 #10 LDZ BRK");
+    assert_eq!(uxn.ram[0x10..0x12], [0x12, 0x34]);
+    assert_eq!(stack_contents(&uxn.wst), [0x12, 0x34, 0x12]);
+
+    let uxn = run("|0100 #abcd #ff STZ2 BRK");
+    assert_eq!(uxn.ram[0xff..0x101], [0xab, 0xcd]);
+    assert_eq!(uxn.ram[0x00], 0x00);
+
+    let mut uxn = Uxn::new();
+    uxn.boot();
+    uxn.load_program(
+        &crate::assembler::assemble("|0100 #ff LDZ2 BRK")
+            .unwrap()
+            .rom,
+        0x100,
+    );
+    uxn.memory_mut()[0xff] = 0x12;
+    uxn.eval(0x100).unwrap();
+    // the low byte is the first byte of the program, the LIT opcode
+    assert_eq!(stack_contents(&uxn.wst), [0x12, 0x80]);
+}
+
+#[test]
+fn halt_through_system_state() {
+    let uxn = run("|0100 #01 #0f DEO #ff #00 STZ BRK");
+    assert!(uxn.is_halted());
+    assert_eq!(uxn.pc(), 0x0105);
+    // nothing after the halt ran
+    assert_eq!(uxn.ram[0x00], 0x00);
+
+    let uxn = run("|0100 #ff #00 STZ BRK");
+    assert!(!uxn.is_halted());
+    assert_eq!(uxn.pc(), 0x0106);
+}
+
+#[test]
+fn nothing_runs_after_a_halt() {
+    let mut uxn = run("|0100 #01 #0f DEO #ff #00 STZ BRK");
+    assert_eq!(uxn.eval_budget(100), (EvalOutcome::Halted, 0));
+    assert_eq!(uxn.step(), EvalOutcome::Halted);
+    assert_eq!(uxn.step_over(), EvalOutcome::Halted);
+    assert_eq!(uxn.pc(), 0x0105);
+    assert_eq!(uxn.ram[0x00], 0x00);
+}
+
+#[test]
+fn shifts_stay_within_width() {
+    for (src, result) in [
+        ("#01 #70 SFT", &[0x80][..]),
+        ("#80 #01 SFT", &[0x40]),
+        ("#ff #00 SFT", &[0xff]),
+        ("#ff #11 SFT", &[0xfe]),
+        ("#ff #80 SFT", &[0x00]),
+        ("#ff #0f SFT", &[0x00]),
+        ("LIT2 0001 #f0 SFT2", &[0x80, 0x00]),
+        ("#8000 #0f SFT2", &[0x00, 0x01]),
+        ("#1234 #34 SFT2", &[0x09, 0x18]),
+        ("#ffff #00 SFT2", &[0xff, 0xff]),
+        ("#ffff #ff SFT2", &[0x80, 0x00]),
+    ] {
+        let uxn = run(&format!("|0100 {} BRK", src));
+        assert_eq!(stack_contents(&uxn.wst), result, "{}", src);
+    }
+}
+
+#[test]
+fn disassemble_with_symbols() {
+    let assembled =
+        crate::assembler::assemble("|0100 @main ;main/loop JMP2 &loop #01 #18 DEO2k BRK").unwrap();
+    let mut uxn = Uxn::new();
+    uxn.boot();
+    uxn.load_program(&assembled.rom, 0x100);
+    // a relative jump over the BRK: LIT 01 JMP BRK
+    uxn.load_program(&[0x80, 0x01, 0x0c, 0x00], 0x200);
+    assert_eq!(uxn.disassemble_at(0x100), ("#0104".to_string(), 3));
+
+    uxn.load_symbols(assembled.symbols);
+    uxn.load_symbols(vec![(0x0204, "skip".to_string())]);
+    assert_eq!(
+        uxn.disassemble(0x100, 0x10a),
+        vec![
+            (0x100, ";main/loop".to_string()),
+            (0x103, "JMP2".to_string()),
+            (0x104, "#01".to_string()),
+            (0x106, "#18".to_string()),
+            (0x108, "DEO2k".to_string()),
+            (0x109, "BRK".to_string()),
+        ]
+    );
+    assert_eq!(uxn.disassemble_at(0x200), (",skip".to_string(), 2));
+}
+
+#[test]
+fn instruction_mode_predicates() {
+    let mode = InstructionMode::from(0xa0);
+    assert!(mode.is_short() && mode.is_keep() && !mode.is_return());
+    let mode = InstructionMode::Return;
+    assert!(!mode.is_short() && !mode.is_keep() && mode.is_return());
+    assert!(!InstructionMode::None.is_short());
+
+    assert_eq!(InstructionMode::None.other_stack(), InstructionMode::Return);
+    assert_eq!(InstructionMode::Return.other_stack(), InstructionMode::None);
+    assert_eq!(
+        (InstructionMode::Short | InstructionMode::Keep).other_stack(),
+        InstructionMode::Short | InstructionMode::Keep | InstructionMode::Return
+    );
+    assert_eq!(
+        (InstructionMode::Short | InstructionMode::Return).other_stack(),
+        InstructionMode::Short
+    );
+}
+
+#[test]
+fn dump_and_load_devices() {
+    let mut uxn = run("|0100 #2a #08 DEO #1234 #0a DEO2 BRK");
+    let dump = uxn.dump_devices();
+    assert_eq!(dump[0x08], 0x2a);
+
+    uxn.boot();
+    assert_eq!(uxn.dump_devices(), [0; 256]);
+    uxn.load_devices(&dump);
+    let rom = crate::assembler::assemble("|0100 #08 DEI #00 STZ BRK").unwrap();
+    uxn.load_program(&rom.rom, 0x100);
+    uxn.eval(0x100).unwrap();
+    assert_eq!(uxn.ram[0x00], 0x2a);
+    assert_eq!(uxn.dump_devices(), dump);
+}
+
+#[test]
+fn step_over_subroutine_call() {
+    let mut uxn = Uxn::new();
+    uxn.boot();
+    let rom = crate::assembler::assemble(
+        "|0100 ;sub JSR2 #01 BRK
+        @sub #02 #03 ADD POP JMP2r",
+    )
+    .unwrap();
+    uxn.load_program(&rom.rom, 0x100);
+    uxn.set_pc(0x100);
+
+    // not a call, steps a single instruction
+    assert_eq!(uxn.step_over(), EvalOutcome::Stepped);
+    assert_eq!(uxn.pc(), 0x103);
+    assert_eq!(uxn.step_over(), EvalOutcome::Stepped);
+    assert_eq!(uxn.pc(), 0x104);
+    assert_eq!(stack_contents(&uxn.wst), &[] as &[u8]);
+    assert_eq!(stack_contents(&uxn.rst), &[] as &[u8]);
+    assert_eq!(uxn.step_over(), EvalOutcome::Stepped);
+    assert_eq!(uxn.step_over(), EvalOutcome::Break);
+
+    // JSI to a routine at 0x0104 returning with JMP2r
+    uxn = Uxn::new();
+    uxn.boot();
+    uxn.load_program(&[JSI, 0x00, 0x01, 0x00, 0x80, 0x02, 0x6c], 0x100);
+    uxn.set_pc(0x100);
+    assert_eq!(uxn.step_over(), EvalOutcome::Stepped);
+    assert_eq!(uxn.pc(), 0x103);
+    assert_eq!(stack_contents(&uxn.wst), &[0x02]);
+    assert_eq!(stack_contents(&uxn.rst), &[] as &[u8]);
+}
+
+#[test]
+fn fault_on_division_by_zero() {
+    let mut uxn = Uxn::new();
+    uxn.boot();
+    let rom = crate::assembler::assemble("|0100 #01 #02 #00 DIV BRK").unwrap();
+    uxn.load_program(&rom.rom, 0x100);
+
+    let fault = uxn.eval(0x100).unwrap_err();
+    assert_eq!(
+        fault,
+        Fault {
+            error: UxnError::DivisionByZero,
+            pc: 0x106,
+            opcode: 0x1b,
+            wst_ptr: 1,
+            rst_ptr: 0,
+        }
+    );
+
+    uxn.pc = 0x100;
+    uxn.wst.ptr = 0;
+    let outcomes: Vec<EvalOutcome> = (0..4).map(|_| uxn.step()).collect();
+    assert_eq!(outcomes[3], EvalOutcome::Error(fault));
+}
+
+#[test]
+fn run_frame_advances_screen_and_audio() {
+    use crate::devices::ConsoleDevice;
+
+    let mut uxn = Uxn::new();
+    uxn.boot();
+    uxn.set_device(devices::SCREEN, Box::new(ScreenDevice::new(64, 64)));
+    uxn.set_device(devices::AUDIO[0], Box::new(AudioDevice::new()));
+    uxn.set_device(devices::CONSOLE, Box::new(ConsoleDevice::with_capture()));
+    let rom = crate::assembler::assemble(
+        "|0100
+            ;on-frame #21 DEO #20 DEO
+            ;on-console #11 DEO #10 DEO
+            ( one fifteenth of a second attack and release )
+            #01 #38 DEO #01 #39 DEO
+        BRK
+        @on-console
+            #00 #28 DEO #00 #29 DEO #00 #2a DEO #00 #2b DEO #02 #2e DEO
+        BRK
+        @on-frame
+            #00 #28 DEO #10 #29 DEO #00 #2a DEO #08 #2b DEO #41 #2e DEO
+            #3c #3f DEO
+        BRK",
+    )
+    .unwrap();
+    uxn.load_program(&rom.rom, 0x100);
+    uxn.eval(0x100).unwrap();
+    uxn.queue_input(devices::CONSOLE);
+
+    let frame = uxn.run_frame(735).unwrap();
+    assert_eq!(
+        frame,
+        FrameResult {
+            halted: false,
+            screen_dirty: true,
+            audio_samples: 735,
+        }
+    );
+    let screen = uxn
+        .device(devices::SCREEN)
+        .downcast_ref::<ScreenDevice>()
+        .unwrap();
+    assert_eq!(screen.pixel(0, 0), 2);
+    assert_eq!(screen.pixel(0x10, 0x08), 1);
+    let audio = uxn
+        .device(devices::AUDIO[0])
+        .downcast_ref::<AudioDevice>()
+        .unwrap();
+    assert!(audio.is_playing());
+}
+
+#[test]
+fn unknown_device() {
+    let mut uxn = Uxn::new();
+    uxn.boot();
+    let rom = crate::assembler::assemble("|0100 #01 #a8 DEO BRK").unwrap();
+    uxn.load_program(&rom.rom, 0x100);
+    let fault = uxn.eval(0x100).unwrap_err();
+    assert_eq!(fault.error, UxnError::UnknownDevice(0xa));
+
+    let rom = crate::assembler::assemble("|0100 #f2 DEI BRK").unwrap();
+    uxn.load_program(&rom.rom, 0x100);
+    let fault = uxn.eval(0x100).unwrap_err();
+    assert_eq!(fault.error, UxnError::UnknownDevice(0xf));
+}
+
+#[test]
+fn opcode_stack_effects() {
+    use InstructionMode as M;
+    assert_eq!(Opcode::ADD.stack_effect(M::None), (2, 1));
+    assert_eq!(Opcode::ADD.stack_effect(M::Short), (4, 2));
+    assert_eq!(Opcode::ADD.stack_effect(M::Keep), (0, 1));
+    assert_eq!(Opcode::DUP.stack_effect(M::None), (1, 2));
+    assert_eq!(Opcode::LIT.stack_effect(M::Keep), (0, 1));
+    assert_eq!(Opcode::LIT.stack_effect(M::Keep | M::Short), (0, 2));
+    assert_eq!(Opcode::LIT.stack_effect(M::None), (0, 0));
+    assert_eq!(Opcode::EQU.stack_effect(M::Short), (4, 1));
+    assert_eq!(Opcode::JCN.stack_effect(M::Short), (3, 0));
+    assert_eq!(Opcode::STA.stack_effect(M::Short), (4, 0));
+}
+
+#[test]
+fn step_jumps() {
+    let jump = |instr: u8, push: &dyn Fn(&mut Uxn)| {
+        let mut uxn = Uxn::new();
+        uxn.boot();
+        uxn.ram[0x200] = instr;
+        push(&mut uxn);
+        uxn.set_pc(0x200);
+        assert_eq!(uxn.step(), EvalOutcome::Stepped);
+        assert_eq!(stack_contents(&uxn.wst), &[] as &[u8]);
+        uxn.pc()
+    };
+    let jmp = Opcode::JMP as u8;
+    let jcn = Opcode::JCN as u8;
+    let short = InstructionMode::Short.bits();
+
+    // relative jumps count from after the JMP
+    assert_eq!(
+        jump(jmp, &|u| u.push8(0x10, InstructionMode::None).unwrap()),
+        0x211
+    );
+    assert_eq!(
+        jump(jmp, &|u| u.push8(0xf0, InstructionMode::None).unwrap()),
+        0x1f1
+    );
+    assert_eq!(
+        jump(jmp, &|u| u.push8(0xff, InstructionMode::None).unwrap()),
+        0x200
+    );
+    assert_eq!(
+        jump(jmp | short, &|u| u
+            .push16(0x1234, InstructionMode::None)
+            .unwrap()),
+        0x1234
+    );
+
+    let conditional = |condition: u16| {
+        move |u: &mut Uxn| {
+            u.push8(condition, InstructionMode::None).unwrap();
+            u.push16(0x1234, InstructionMode::None).unwrap();
+        }
+    };
+    assert_eq!(jump(jcn | short, &conditional(1)), 0x1234);
+    assert_eq!(jump(jcn | short, &conditional(0)), 0x201);
+}
+
+#[test]
+fn bitwise_in_both_widths() {
+    for (src, result) in [
+        ("#f0 #0f ORA", &[0xff][..]),
+        ("#f0 #3c AND", &[0x30]),
+        ("#ff #0f EOR", &[0xf0]),
+        ("#ff00 LIT2 00ff AND2", &[0x00, 0x00]),
+        ("#ff00 LIT2 00ff ORA2", &[0xff, 0xff]),
+        ("#ffff #0f0f EOR2", &[0xf0, 0xf0]),
+        ("#1234 #ff00 AND2k", &[0x12, 0x34, 0xff, 0x00, 0x12, 0x00]),
+    ] {
+        let uxn = run(&format!("|0100 {} BRK", src));
+        assert_eq!(stack_contents(&uxn.wst), result, "{}", src);
+    }
+}
+
+#[test]
+fn render_frame_formats() {
+    use crate::devices::{self, PixelFormat, ScreenDevice};
+
+    let mut uxn = Uxn::new();
+    uxn.set_device(devices::SCREEN, Box::new(ScreenDevice::new(4, 2)));
+    // palette 0 black, 1 red, 2 green, 3 blue; a pixel of color 1 at (2, 1)
+    let rom = crate::assembler::assemble(
+        "|0100
+            #0f00 #08 DEO2 #00f0 #0a DEO2 #000f #0c DEO2
+            #0002 #28 DEO2 #0001 #2a DEO2 #01 #2e DEO
+        BRK",
+    )
+    .unwrap();
+    uxn.load_program(&rom.rom, 0x100);
+    uxn.eval(0x100).unwrap();
+    assert_eq!(
+        uxn.palette(),
+        [[0, 0, 0], [0xff, 0, 0], [0, 0xff, 0], [0, 0, 0xff]]
+    );
+
+    let screen = uxn
+        .device(devices::SCREEN)
+        .downcast_ref::<ScreenDevice>()
+        .unwrap();
+    let render = |format| screen.render_frame(&uxn.palette(), format);
+    let i = 4 + 2;
+    let argb = render(PixelFormat::Argb8888);
+    assert_eq!(argb.len(), 4 * 2 * 4);
+    assert_eq!(&argb[i * 4..i * 4 + 4], &[0xff, 0xff, 0x00, 0x00]);
+    assert_eq!(&argb[..4], &[0xff, 0x00, 0x00, 0x00]);
+    let rgba = render(PixelFormat::Rgba8888);
+    assert_eq!(&rgba[i * 4..i * 4 + 4], &[0xff, 0x00, 0x00, 0xff]);
+    let indexed = render(PixelFormat::Indexed8);
+    assert_eq!(indexed, [0, 0, 0, 0, 0, 0, 1, 0]);
+}
+
+#[test]
+fn boot_rom_and_eval() {
+    let rom = crate::assembler::assemble("|0100 #01 #02 ADD BRK")
+        .unwrap()
+        .rom;
+    let mut uxn = Uxn::new();
+    uxn.push8(0xff, InstructionMode::None).unwrap();
+    let entry = uxn.boot_rom(&rom).unwrap();
+    assert_eq!(entry, 0x0100);
+    uxn.eval(entry).unwrap();
+    assert_eq!(stack_contents(&uxn.wst), &[0x03]);
+
+    assert_eq!(uxn.boot_rom(&[0; 0xff01]), Err(UxnError::OutOfBounds));
+    assert!(uxn.boot_rom(&[0; 0xff00]).is_ok());
+    assert!(matches!(
+        Uxn::try_from(&[0; 0xff01][..]),
+        Err(UxnError::OutOfBounds)
+    ));
+}
+
+#[test]
+fn fill_screen_quadrants() {
+    use crate::devices::ScreenDevice;
+
+    // fills a 4x3 screen from (2, 1) with color 1 towards the corner selected by the flip flags
+    let fill = |flags: u8| {
+        let mut screen = ScreenDevice::new(4, 3);
+        for (port, value) in [(0x09, 2), (0x0b, 1), (0x0e, 0x81 | flags)] {
+            screen.deo(port, value).unwrap();
+        }
+        let pixels: Vec<u8> = (0..3)
+            .flat_map(|y| (0..4).map(move |x| (x, y)))
+            .map(|(x, y)| screen.pixel(x, y))
+            .collect();
+        pixels
+    };
+    #[rustfmt::skip]
+    let quadrants: [(u8, [u8; 12]); 4] = [
+        (0x00, [0, 0, 0, 0,
+                0, 0, 1, 1,
+                0, 0, 1, 1]),
+        (0x10, [0, 0, 0, 0,
+                1, 1, 0, 0,
+                1, 1, 0, 0]),
+        (0x20, [0, 0, 1, 1,
+                0, 0, 0, 0,
+                0, 0, 0, 0]),
+        (0x30, [1, 1, 0, 0,
+                0, 0, 0, 0,
+                0, 0, 0, 0]),
+    ];
+    for (flags, expected) in quadrants {
+        assert_eq!(fill(flags), expected, "flags {:02x}", flags);
+    }
+
+    // clearing the whole foreground in one write
+    let mut screen = ScreenDevice::new(4, 3);
+    screen.deo(0x0e, 0xc3).unwrap();
+    assert_eq!(screen.pixel(3, 2), 3);
+    screen.deo(0x0e, 0xc0).unwrap();
+    assert_eq!(screen.pixel(3, 2), 0);
+}
+
+#[test]
+fn resize_the_screen() {
+    use crate::devices::{self, PixelFormat, ScreenDevice};
+
+    let mut uxn = Uxn::try_from(
+        "|0100 #0001 #28 DEO2 #0002 #2a DEO2 #03 #2e DEO
+        #000c #22 DEO2 #0018 #24 DEO2 BRK",
+    )
+    .unwrap();
+    assert_eq!(uxn.screen_size(), (0, 0));
+    uxn.set_device(devices::SCREEN, Box::new(ScreenDevice::new(16, 16)));
+    uxn.eval(0x100).unwrap();
+
+    assert_eq!(uxn.screen_size(), (12, 24));
+    let screen = uxn
+        .device(devices::SCREEN)
+        .downcast_ref::<ScreenDevice>()
+        .unwrap();
+    let palette = [[0; 3]; 4];
+    assert_eq!(
+        screen.render_frame(&palette, PixelFormat::Indexed8).len(),
+        12 * 24
+    );
+    // the pixel drawn before the resize stays where it was
+    assert_eq!(screen.pixel(1, 2), 3);
+    assert_eq!(screen.pixel(11, 23), 0);
+    assert_eq!(screen.dei(0x03), Ok(12));
+}
+
+#[test]
+fn oversized_screens_are_ignored() {
+    use crate::devices::{self, ScreenDevice};
+
+    for src in ["#ffff #22 DEO2", "#ffff #24 DEO2", "#0004 #22 DEO2"] {
+        let mut uxn = Uxn::try_from(src).unwrap();
+        uxn.set_device(devices::SCREEN, Box::new(ScreenDevice::new(16, 16)));
+        uxn.eval(0x100).unwrap();
+        assert_eq!(uxn.screen_size(), (16, 16), "{}", src);
+    }
+
+    // a rejected size doesn't linger in the ports' high byte
+    let mut uxn = Uxn::try_from("#ffff #22 DEO2 #20 #23 DEO").unwrap();
+    uxn.set_device(devices::SCREEN, Box::new(ScreenDevice::new(16, 16)));
+    uxn.eval(0x100).unwrap();
+    assert_eq!(uxn.screen_size(), (0x20, 16));
+}
+
+#[test]
+fn sprite_address_wraps_around_memory() {
+    use crate::devices::{ScreenDevice, SCREEN};
+
+    let mut uxn = Uxn::new();
+    // a blank tile at 0xfff8, and a solid one at 0x0000 after the address wraps
+    uxn.set_ram_slice(0x0000, &[0xff; 8]).unwrap();
+    uxn.set_device(SCREEN, Box::new(ScreenDevice::new(8, 16)));
+    // two tiles going down, the address advancing by a tile after each
+    for (addr, value) in [(0x26, 0x15), (0x2c, 0xff), (0x2d, 0xf8), (0x2f, 0x01)] {
+        uxn.device_out(addr, value).unwrap();
+    }
+
+    let screen = uxn.device(SCREEN).downcast_ref::<ScreenDevice>().unwrap();
+    assert_eq!(screen.pixel(0, 0), 0);
+    assert_eq!(screen.pixel(7, 7), 0);
+    assert_eq!(screen.pixel(0, 8), 1);
+    assert_eq!(screen.pixel(7, 15), 1);
+    assert_eq!((screen.dei(0x0c), screen.dei(0x0d)), (Ok(0x00), Ok(0x08)));
+}
+
+#[test]
+fn debug_text_glyphs() {
+    use crate::devices::{self, debug_text, DebugTextDevice, ScreenDevice};
+
+    let mut uxn = Uxn::try_from(
+        "#0002 #e8 DEO2 #0003 #ea DEO2 #01 #ec DEO
+        #41 #ed DEO #78 #ed DEO BRK",
+    )
+    .unwrap();
+    let screen = ScreenDevice::new(24, 16);
+    uxn.set_device(0xe, Box::new(DebugTextDevice::new(screen.framebuffer())));
+    uxn.set_device(devices::SCREEN, Box::new(screen));
+    uxn.eval(0x100).unwrap();
+
+    let screen = uxn
+        .device(devices::SCREEN)
+        .downcast_ref::<ScreenDevice>()
+        .unwrap();
+    // each character is a tile to the right of the previous one
+    for (char, x) in [(b'A', 2), (b'x', 10)] {
+        let tile = debug_text::glyph(char);
+        for (row, bits) in tile.iter().enumerate() {
+            for col in 0..8 {
+                let set = bits >> (7 - col) & 1;
+                assert_eq!(
+                    screen.pixel(x + col, 3 + row as u16),
+                    set,
+                    "{}",
+                    char as char
+                );
+            }
+        }
+    }
+    assert_eq!(debug_text::glyph(b'A')[0], 0x38);
+    assert_eq!(screen.pixel(2, 3), 0);
+    assert_eq!(uxn.device_names().last(), Some(&(0xe, "debug-text")));
+}
+
+#[test]
+fn draw_sprite_from_loaded_tile() {
+    use crate::devices::{self, ScreenDevice};
+
+    let mut uxn = Uxn::new();
+    uxn.boot();
+    uxn.set_device(devices::SCREEN, Box::new(ScreenDevice::new(16, 16)));
+    // a hollow square
+    let tile = [0xff, 0x81, 0x81, 0x81, 0x81, 0x81, 0x81, 0xff];
+    uxn.set_ram_slice(0x2000, &tile).unwrap();
+    let rom = crate::assembler::assemble(
+        "|0100
+            #00 #28 DEO #04 #29 DEO
+            #00 #2a DEO #02 #2b DEO
+            #20 #2c DEO #00 #2d DEO
+            #05 #2f DEO
+        BRK",
+    )
+    .unwrap();
+    uxn.load_program(&rom.rom, 0x100);
+    uxn.eval(0x100).unwrap();
+
+    let screen = uxn
+        .device(devices::SCREEN)
+        .downcast_ref::<ScreenDevice>()
+        .unwrap();
+    let row = |y| (0..16).map(|x| screen.pixel(x, y)).collect::<Vec<_>>();
+    // blending mode 5 draws 1 pixels in color 1 and leaves 0 pixels transparent
+    assert_eq!(row(1), vec![0; 16]);
+    assert_eq!(row(2), [&[0; 4][..], &[1; 8], &[0; 4]].concat());
+    assert_eq!(row(3), [&[0; 4][..], &[1], &[0; 6], &[1], &[0; 4]].concat());
+    assert_eq!(row(9), row(2));
+    assert_eq!(row(10), vec![0; 16]);
+
+    assert_eq!(uxn.set_ram_slice(0xffff, &[0xaa, 0xbb]), Ok(()));
+    assert_eq!((uxn.ram[0xffff], uxn.ram[0x0000]), (0xaa, 0xbb));
+    assert_eq!(
+        uxn.set_ram_slice(0, &vec![0; 0x10001]),
+        Err(UxnError::OutOfBounds)
+    );
+}
+
+#[test]
+fn construct_from_rom_and_source() {
+    let mut uxn = Uxn::try_from(&[0x80, 0x01, 0x01, 0x00][..]).unwrap();
+    uxn.eval(0x100).unwrap();
+    assert_eq!(stack_contents(&uxn.wst), &[0x02]);
+
+    let mut uxn = Uxn::try_from("#01 #02 ADD").unwrap();
+    uxn.eval(0x100).unwrap();
+    assert_eq!(stack_contents(&uxn.wst), &[0x03]);
+
+    let mut uxn = Uxn::try_from("|0100 @main #01 #02 ADD").unwrap();
+    uxn.eval(0x100).unwrap();
+    assert_eq!(stack_contents(&uxn.wst), &[0x03]);
+
+    assert!(matches!(
+        Uxn::try_from("#01 ;nowhere"),
+        Err(AsmError::UnknownLabel { .. })
+    ));
+}
+
+#[test]
+fn system_ports() {
+    let wst = |src: &str| stack_contents(&run(&format!("|0100 {} BRK", src)).wst).to_vec();
+
+    assert_eq!(wst("#aa #bb #02 DEI #03 DEI"), [0xaa, 0xbb, 0x02, 0x00]);
+    assert_eq!(wst("#aa #bb #cc #01 #02 DEO"), [0xaa]);
+    assert_eq!(
+        stack_contents(&run("|0100 #02 #03 DEO BRK").rst),
+        [0x00, 0x00]
+    );
+    // vector, metadata and palette are plain device memory
+    assert_eq!(
+        wst(
+            "#12 #00 DEO #34 #01 DEO #56 #06 DEO #78 #07 DEO #f0 #08 DEO #7f #0d DEO
+            #00 DEI #01 DEI #06 DEI #07 DEI #08 DEI #0d DEI"
+        ),
+        [0x12, 0x34, 0x56, 0x78, 0xf0, 0x7f]
+    );
+    assert_eq!(wst("#01 #0e DEO #01"), [0x01]);
+    let uxn = run("|0100 #01 #0f DEO #ff BRK");
+    assert!(uxn.is_halted());
+    assert_eq!(stack_contents(&uxn.wst), &[] as &[u8]);
+
+    for src in ["#04 DEI", "#01 #05 DEO"] {
+        let mut uxn = Uxn::try_from(src).unwrap();
+        let fault = uxn.eval(0x100).unwrap_err();
+        let unmapped = UxnError::Device {
+            name: "system",
+            reason: "unmapped port",
+        };
+        assert_eq!(fault.error, unmapped, "{}", src);
+    }
+}
+
+#[test]
+fn system_stack_pointers_as_a_short() {
+    // the port is popped before the pointers are read
+    let uxn = run("|0100 #aa LITr bb #02 DEI2 BRK");
+    assert_eq!(stack_contents(&uxn.wst), [0xaa, 0x01, 0x01]);
+
+    let uxn = run("|0100 #aa #bb #cc #0201 #02 DEO2 BRK");
+    assert_eq!(stack_contents(&uxn.wst), [0xaa, 0xbb]);
+    assert_eq!(uxn.rst.ptr, 1);
+}
+
+#[test]
+fn eval_with_device_hooks() {
+    use crate::devices::{ConsoleDevice, CONSOLE};
+
+    let mut uxn = Uxn::try_from("#12 DEI #18 DEO #00 DEI #2a #19 DEO").unwrap();
+    uxn.set_device(CONSOLE, Box::new(ConsoleDevice::with_capture()));
+    let mut writes = vec![];
+    uxn.eval_with_hooks(
+        0x100,
+        |slot, port, value| writes.push((slot, port, value)),
+        |slot, port| (slot == CONSOLE).then_some(0x41 + port),
+    )
+    .unwrap();
+
+    assert_eq!(writes, vec![(0x1, 0x8, 0x43), (0x1, 0x9, 0x2a)]);
+    assert_eq!(stack_contents(&uxn.wst), &[0x00]);
+    let console = uxn.device(CONSOLE).downcast_ref::<ConsoleDevice>().unwrap();
+    assert_eq!(console.captured(), b"C");
+}
+
+#[test]
+fn rot_rotates_whole_values() {
+    for (src, result) in [
+        ("#11 #22 #33 ROT", &[0x22, 0x33, 0x11][..]),
+        (
+            "LIT2 0011 LIT2 0022 LIT2 0033 ROT2",
+            &[0x00, 0x22, 0x00, 0x33, 0x00, 0x11],
+        ),
+        (
+            "#1122 #3344 #5566 ROT2",
+            &[0x33, 0x44, 0x55, 0x66, 0x11, 0x22],
+        ),
+    ] {
+        let uxn = run(&format!("|0100 {} BRK", src));
+        assert_eq!(stack_contents(&uxn.wst), result, "{}", src);
+    }
+}
+
+#[test]
+fn enlarged_stacks() {
+    let mut uxn = Uxn::with_stack_size(1024);
+    uxn.boot();
+    for i in 0..600 {
+        uxn.push8(i as u16, InstructionMode::None).unwrap();
+    }
+    uxn.push16(0x1234, InstructionMode::Return).unwrap();
+    assert_eq!(uxn.wst.ptr, 600);
+    assert_eq!(uxn.pop16(InstructionMode::None), Ok(0x5657));
+
+    let mut uxn = Uxn::new();
+    uxn.boot();
+    for i in 0..255 {
+        uxn.push8(i, InstructionMode::None).unwrap();
+    }
+    assert_eq!(
+        uxn.push8(0xff, InstructionMode::None),
+        Err(UxnError::StackOverflow {
+            stack: StackId::Working,
+            ptr: 255
+        })
+    );
+}
+
+#[test]
+fn runaway_recursion_overflows_the_return_stack() {
+    let mut uxn = Uxn::try_from("@recurse ,recurse JSR").unwrap();
+    let fault = uxn.eval(0x100).unwrap_err();
+    assert_eq!(
+        fault.error,
+        UxnError::StackOverflow {
+            stack: StackId::Return,
+            ptr: 254
+        }
+    );
+    assert_eq!(fault.error.to_string(), "return stack overflow at fe");
+}
+
+#[test]
+fn eval_by_label() {
+    let assembled = crate::assembler::assemble(
+        "|0100 BRK
+        @test-add #01 #02 ADD BRK
+        @test-sub #05 #03 SUB BRK",
+    )
+    .unwrap();
+    let mut uxn = Uxn::try_from(assembled.rom.as_slice()).unwrap();
+    uxn.load_symbols(assembled.symbols);
+
+    uxn.eval_label("test-add").unwrap();
+    assert_eq!(stack_contents(&uxn.wst), &[0x03]);
+    uxn.eval_label("test-sub").unwrap();
+    assert_eq!(stack_contents(&uxn.wst), &[0x03, 0x02]);
+    assert_eq!(
+        uxn.eval_label("test-mul"),
+        Err(EvalLabelError::UnknownLabel("test-mul".to_string()))
+    );
+}
+
+#[test]
+fn run_on_another_thread() {
+    use crate::devices::{ConsoleDevice, CONSOLE};
+
+    let mut uxn = Uxn::try_from("#68 #18 DEO #69 #18 DEO").unwrap();
+    uxn.set_device(CONSOLE, Box::new(ConsoleDevice::with_capture()));
+    let uxn = std::thread::spawn(move || {
+        uxn.eval(0x100).unwrap();
+        uxn
+    })
+    .join()
+    .unwrap();
+    let console = uxn.device(CONSOLE).downcast_ref::<ConsoleDevice>().unwrap();
+    assert_eq!(console.captured(), b"hi");
+}
+
+#[test]
+fn instruction_byte_round_trip() {
+    for byte in 0..=255u8 {
+        let (opcode, mode) = decode_byte(byte);
+        assert_eq!(encode_byte(opcode, mode), byte);
+    }
+    assert_eq!(
+        decode_byte(0xb8),
+        (Opcode::ADD, InstructionMode::Keep | InstructionMode::Short)
+    );
+    assert_eq!(encode_byte(Opcode::LIT, InstructionMode::Keep), 0x80);
+}
+
+#[test]
+fn all_opcodes() {
+    let all = Opcode::all();
+    assert_eq!(all.len(), 32);
+    for (byte, opcode) in all.iter().enumerate() {
+        assert_eq!(*opcode as usize, byte);
+    }
+}
+
+#[test]
+fn bad_opcodes() {
+    assert_eq!(Opcode::try_from(0x1f), Ok(Opcode::SFT));
+    assert_eq!(Opcode::try_from(0xff), Err(UxnError::BadOpcode(0xff)));
+    assert_eq!(
+        UxnError::BadOpcode(0xff).to_string(),
+        "bad opcode 0xff (mode: 2kr, op-bits: 0x1f)"
+    );
+    assert_eq!(
+        UxnError::BadOpcode(0x38).to_string(),
+        "bad opcode 0x38 (mode: 2, op-bits: 0x18)"
+    );
+    // every byte is an instruction, data run as code decodes into something
+    for byte in 0..=255u8 {
+        assert!(Opcode::try_from(byte & 0x1f).is_ok());
+    }
+}
+
+#[test]
+fn mode_without_opcode_bits() {
+    // ADD2, the opcode bits 0x18 must not leak into the mode
+    let mode = InstructionMode::from_instruction(0x38);
+    assert_eq!(mode, InstructionMode::Short);
+    assert_eq!(mode.bits(), 0x20);
+    assert_eq!(decode_byte(0x38), (Opcode::ADD, InstructionMode::Short));
+    assert_eq!(
+        InstructionMode::from_instruction(0x1f),
+        InstructionMode::None
+    );
+}
+
+#[test]
+fn call_and_return_hooks() {
+    #[derive(Default)]
+    struct CallTrace(Vec<(&'static str, u16)>);
+
+    impl EvalHooks for CallTrace {
+        fn on_call(&mut self, addr: u16) {
+            self.0.push(("call", addr));
+        }
+        fn on_return(&mut self, addr: u16) {
+            self.0.push(("return", addr));
+        }
+    }
+
+    let assembled = crate::assembler::assemble(
+        "|0100 ;outer JSR2 BRK
+        @outer ;inner JSR2 ,&near JSR &near JMP2r
+        @inner JMP2r",
+    )
+    .unwrap();
+    let mut uxn = Uxn::try_from(assembled.rom.as_slice()).unwrap();
+    let mut trace = CallTrace::default();
+    uxn.eval_with(0x100, &mut trace).unwrap();
+
+    assert_eq!(
+        trace.0,
+        vec![
+            ("call", 0x105),
+            ("call", 0x10d),
+            ("return", 0x109),
+            ("call", 0x10c),
+            ("return", 0x10c),
+            ("return", 0x104),
+        ]
+    );
+}
+
+#[test]
+fn lit_wraps_around_memory() {
+    let mut uxn = Uxn::new();
+    uxn.boot();
+    // LIT2 at 0xfffe, its operand straddling the end of memory
+    uxn.set_ram_slice(0xfffe, &[0xa0, 0x12, 0x34, 0x80, 0x56, 0x00])
+        .unwrap();
+    uxn.set_pc(0xfffe);
+    assert_eq!(uxn.step(), EvalOutcome::Stepped);
+    assert_eq!(uxn.pc(), 0x0001);
+    assert_eq!(uxn.step(), EvalOutcome::Stepped);
+    assert_eq!(uxn.step(), EvalOutcome::Break);
+    assert_eq!(stack_contents(&uxn.wst), &[0x12, 0x34, 0x56]);
+
+    // a LIT as the very last byte reads its operand from 0x0000
+    uxn.set_ram_slice(0xffff, &[0x80, 0x78]).unwrap();
+    uxn.set_pc(0xffff);
+    assert_eq!(uxn.step(), EvalOutcome::Stepped);
+    assert_eq!(uxn.pc(), 0x0001);
+    assert_eq!(stack_contents(&uxn.wst), &[0x12, 0x34, 0x56, 0x78]);
+}
+
+#[test]
+fn push_and_pop_bytes() {
+    let mut uxn = Uxn::with_stack_size(6);
+    uxn.push_bytes(&[1, 2, 3, 4]).unwrap();
+    assert_eq!(stack_contents(&uxn.wst), &[1, 2, 3, 4]);
+    assert_eq!(
+        uxn.push_bytes(&[5, 6]),
+        Err(UxnError::StackOverflow {
+            stack: StackId::Working,
+            ptr: 4
+        })
+    );
+    assert_eq!(stack_contents(&uxn.wst), &[1, 2, 3, 4]);
+
+    assert_eq!(uxn.pop_bytes(5), Err(UxnError::StackUnderflow));
+    assert_eq!(uxn.pop_bytes(4), Ok(vec![4, 3, 2, 1]));
+    assert_eq!(stack_contents(&uxn.wst), &[]);
+}
+
+#[test]
+fn stack_pointer_past_a_small_stack() {
+    let mut uxn = Uxn::with_stack_size(6);
+    uxn.boot();
+    uxn.load_program(&[0x80, 0xff, 0x80, 0x02, 0x17, 0x02], 0x100);
+    let fault = uxn.eval(0x100).unwrap_err();
+    assert_eq!(
+        fault.error,
+        UxnError::StackOverflow {
+            stack: StackId::Working,
+            ptr: 0xff
+        }
+    );
+    assert_eq!(uxn.wst.ptr, 0);
+
+    // the last byte of the stack is still a valid pointer
+    uxn = Uxn::with_stack_size(6);
+    uxn.boot();
+    uxn.load_program(&[0x80, 0x05, 0x80, 0x03, 0x17, 0x00], 0x100);
+    uxn.eval(0x100).unwrap();
+    assert_eq!(uxn.rst.ptr, 5);
+}
+
+#[test]
+fn checked_arithmetic() {
+    let mut uxn = Uxn::try_from("#ff #01 ADD BRK").unwrap();
+    uxn.eval(0x100).unwrap();
+    assert_eq!(stack_contents(&uxn.wst), &[0x00]);
+
+    for src in [
+        "#ff #01 ADD BRK",
+        "#00 #01 SUB BRK",
+        "#10 #10 MUL BRK",
+        "#ffff INC2 BRK",
+    ] {
+        uxn = Uxn::try_from(src).unwrap();
+        uxn.set_arithmetic_mode(ArithmeticMode::Checked);
+        let fault = uxn.eval(0x100).unwrap_err();
+        assert_eq!(fault.error, UxnError::ArithmeticOverflow);
+        assert_eq!(fault.pc, if src.contains("INC2") { 0x103 } else { 0x104 });
+    }
+
+    uxn = Uxn::try_from("#fe #01 ADD #02 #01 SUB #10 #0f MUL BRK").unwrap();
+    uxn.set_arithmetic_mode(ArithmeticMode::Checked);
+    uxn.eval(0x100).unwrap();
+    assert_eq!(stack_contents(&uxn.wst), &[0xff, 0x01, 0xf0]);
+}
+
+#[test]
+fn exec_guard() {
+    let src = "#0040 JMP2 BRK";
+    let mut uxn = Uxn::try_from(src).unwrap();
+    uxn.set_exec_guard(true);
+    let fault = uxn.eval(0x100).unwrap_err();
+    assert_eq!(fault.error, UxnError::ExecZeroPage(0x0040));
+    assert_eq!(fault.pc, 0x0040);
+
+    // without the guard the zero page runs, here straight into a BRK
+    uxn = Uxn::try_from(src).unwrap();
+    uxn.eval(0x100).unwrap();
+    assert_eq!(uxn.pc(), 0x0041);
+}
+
+#[test]
+fn entry_guard() {
+    let mut uxn = Uxn::try_from("#8000 JMP2").unwrap();
+    uxn.set_entry_guard(true);
+    let fault = uxn.eval(0x100).unwrap_err();
+    assert_eq!(fault.error, UxnError::ExecUninitialized(0x8000));
+
+    // a stored BRK is written memory, even though it is still a zero
+    uxn = Uxn::try_from("#00 #8000 STA #8000 JMP2").unwrap();
+    uxn.set_entry_guard(true);
+    uxn.eval(0x100).unwrap();
+    assert_eq!(uxn.pc(), 0x8001);
+}
+
+#[test]
+fn entry_guard_sees_device_writes() {
+    /// Stores the byte written to port 0x00 at 0x8000, like a device loading a file into RAM.
+    struct Loader;
+
+    impl Device for Loader {
+        fn dei(&self, _port: PortAddress) -> ExecutionResult<u8> {
+            Err(UxnError::Device {
+                name: self.name(),
+                reason: "unhandled port",
+            })
+        }
+        fn deo(&mut self, _port: PortAddress, _value: u8) -> ExecutionResult<()> {
+            Err(UxnError::Device {
+                name: self.name(),
+                reason: "unhandled port",
+            })
+        }
+        fn deo_ram(&mut self, _port: PortAddress, value: u8, ram: &mut Ram) -> ExecutionResult<()> {
+            ram.set(0x8000, value);
+            Ok(())
+        }
+        fn deo_mask(&self) -> u16 {
+            0x0001
+        }
+        fn name(&self) -> &'static str {
+            "loader"
+        }
+    }
+
+    // the loaded LIT runs and reads its operand, then the PC reaches memory nothing wrote
+    let mut uxn = Uxn::try_from("#80 #a0 DEO #8000 JMP2").unwrap();
+    uxn.set_device(0xa, Box::new(Loader));
+    uxn.set_entry_guard(true);
+    let fault = uxn.eval(0x100).unwrap_err();
+    assert_eq!(fault.error, UxnError::ExecUninitialized(0x8002));
+}
+
+#[test]
+fn relative_load_and_store() {
+    let assembled = crate::assembler::assemble(
+        "|0100 @before 12 34
+        @main
+            ,before LDR ,before LDR2
+            #abcd ,after STR2 ,after LDR2
+            #ef ,after STR ,after LDR
+            BRK
+        @after $2",
+    )
+    .unwrap();
+    let mut uxn = Uxn::try_from(assembled.rom.as_slice()).unwrap();
+    uxn.eval(0x102).unwrap();
+    assert_eq!(
+        stack_contents(&uxn.wst),
+        &[0x12, 0x12, 0x34, 0xab, 0xcd, 0xef]
+    );
+    assert_eq!(uxn.memory()[0x11a..0x11c], [0xef, 0xcd]);
+
+    // offsets wrap around memory
+    uxn.set_ram_slice(0x0010, &[0x80, 0xe0, 0x12, 0x00])
+        .unwrap();
+    uxn.set_ram_slice(0xfff3, &[0x56]).unwrap();
+    uxn.eval(0x0010).unwrap();
+    assert_eq!(stack_contents(&uxn.wst).last(), Some(&0x56));
+}
+
+#[test]
+fn disassemble_code_and_data() {
+    let assembled = crate::assembler::assemble(
+        "|0100 @main #01 ;sprite BRK
+        00 0f 3c 7e ff
+        @sprite 20 00 @tail #02",
+    )
+    .unwrap();
+    let mut uxn = Uxn::try_from(assembled.rom.as_slice()).unwrap();
+    uxn.load_symbols(assembled.symbols);
+    assert_eq!(
+        uxn.disassemble_range_text(0x100, 0x10e),
+        "@main
+0100  80 01        #01
+0102  a0 01 0b     ;sprite
+0105  00           BRK
+0106  00 0f 3c 7e  .db 00 0f 3c 7e
+010a  ff           .db ff
+@sprite
+010b  20 00        .db 20 00
+@tail
+010d  80           .db 80
+"
+    );
+}
+
+#[test]
+fn input_vectors_run_in_priority_order() {
+    use crate::devices::{CONSOLE, CONTROLLER, MOUSE};
+
+    // each vector pushes its slot
+    let mut uxn = Uxn::try_from("#01 BRK #08 BRK #09 BRK").unwrap();
+    let mut dev = [0; 256];
+    dev[0x10..0x12].copy_from_slice(&[0x01, 0x00]);
+    dev[0x80..0x82].copy_from_slice(&[0x01, 0x03]);
+    dev[0x90..0x92].copy_from_slice(&[0x01, 0x06]);
+    uxn.load_devices(&dev);
+
+    uxn.queue_input(MOUSE);
+    uxn.queue_input(CONSOLE);
+    uxn.queue_input(CONTROLLER);
+    uxn.run_frame(0).unwrap();
+    assert_eq!(stack_contents(&uxn.wst), &[0x01, 0x08, 0x09]);
+}
+
+#[test]
+fn assemble_and_run_a_program() {
+    let run = Uxn::assemble_and_run("|0100 #01 #02 ADD #18 DEO BRK").unwrap();
+    assert_eq!(
+        run,
+        RunOutcome {
+            outcome: EvalOutcome::Break,
+            wst: vec![],
+            rst: vec![],
+            console: vec![0x03],
+        }
+    );
+    let run = Uxn::assemble_and_run("#0304 STH2 #05").unwrap();
+    assert_eq!((run.wst, run.rst), (vec![0x05], vec![0x03, 0x04]));
+
+    let run = Uxn::assemble_and_run("#68 #18 DEO #2a #01 #0f DEO").unwrap();
+    assert_eq!(run.outcome, EvalOutcome::Halted);
+    assert_eq!((run.wst, run.console), (vec![0x2a], b"h".to_vec()));
+
+    assert!(matches!(
+        Uxn::assemble_and_run("#01 ;nowhere"),
+        Err(AppError::Asm(AsmError::UnknownLabel { .. }))
+    ));
+    assert!(matches!(
+        Uxn::assemble_and_run("POP"),
+        Err(AppError::Fault(Fault {
+            error: UxnError::StackUnderflow,
+            ..
+        }))
+    ));
+}
+
+#[test]
+fn stop_at_breakpoints() {
+    let mut uxn = Uxn::try_from("#01 #02 ADD #03 MUL #04 BRK").unwrap();
+    uxn.set_breakpoint(0x0107);
+    uxn.set_breakpoint(0x0104);
+    uxn.set_breakpoint(0x0300);
+
+    assert_eq!(
+        uxn.run_to_breakpoint(0x100),
+        Ok(EvalOutcome::Breakpoint(0x0104))
+    );
+    assert_eq!(stack_contents(&uxn.wst), &[0x01, 0x02]);
+    assert_eq!(
+        uxn.run_to_breakpoint(uxn.pc()),
+        Ok(EvalOutcome::Breakpoint(0x0107))
+    );
+    assert_eq!(stack_contents(&uxn.wst), &[0x03, 0x03]);
+    assert_eq!(uxn.run_to_breakpoint(uxn.pc()), Ok(EvalOutcome::Break));
+    assert_eq!(stack_contents(&uxn.wst), &[0x09, 0x04]);
+
+    uxn.clear_breakpoint(0x0104);
+    uxn.reset_stacks();
+    assert_eq!(
+        uxn.run_to_breakpoint(0x100),
+        Ok(EvalOutcome::Breakpoint(0x0107))
+    );
+}
+
+#[test]
+fn resume_after_a_breakpoint() {
+    let mut uxn = Uxn::try_from("#01 #02 ADD #03 MUL #04 BRK").unwrap();
+    uxn.set_breakpoint(0x0104);
+    assert_eq!(
+        uxn.run_to_breakpoint(0x100),
+        Ok(EvalOutcome::Breakpoint(0x0104))
+    );
+    assert_eq!(stack_contents(&uxn.wst), &[0x01, 0x02]);
+
+    // the breakpoint doesn't stop a resumed run
+    assert_eq!(uxn.resume(), Ok(EvalOutcome::Break));
+    assert_eq!(stack_contents(&uxn.wst), &[0x09, 0x04]);
+    assert_eq!(uxn.pc(), 0x010b);
+
+    // a budgeted run continues where it ran out
+    let mut uxn = Uxn::try_from("#01 #02 ADD #03 MUL #04 BRK").unwrap();
+    uxn.set_pc(0x100);
+    assert_eq!(uxn.eval_budget(2), (EvalOutcome::Stepped, 2));
+    assert_eq!(uxn.resume(), Ok(EvalOutcome::Break));
+    assert_eq!(stack_contents(&uxn.wst), &[0x09, 0x04]);
+}
+
+#[test]
+fn trace_control_flow() {
+    let mut uxn = Uxn::try_from(
+        "@main
+            #01 ,&taken JCN
+            #ff
+            &taken #0004 ;double JSR2 POP2 BRK
+        @double DUP2 ADD2 JMP2r",
+    )
+    .unwrap();
+    uxn.set_pc_trace(true);
+    uxn.eval(0x100).unwrap();
+    #[rustfmt::skip]
+    let expected = [
+        0x0100, 0x0102, 0x0104, // #01 ,&taken JCN
+        0x0107, 0x010a, 0x010d, // #0004 ;double JSR2
+        0x0110, 0x0111, 0x0112, // DUP2 ADD2 JMP2r
+        0x010e, 0x010f,         // POP2 BRK
+    ];
+    assert_eq!(uxn.pc_trace(), expected);
+
+    uxn.set_pc_trace(false);
+    uxn.eval(0x100).unwrap();
+    assert_eq!(uxn.pc_trace(), &[] as &[u16]);
+}
+
+#[test]
+fn eval_from_zero() {
+    // #2a at 0x0000
+    let mut uxn = Uxn::new();
+    uxn.set_ram_slice(0x0000, &[0x80, 0x2a, 0x00]).unwrap();
+    // 0x0000 is no vector, eval doesn't run it
+    uxn.eval(0x0000).unwrap();
+    assert_eq!(uxn.pc(), 0x0000);
+    assert_eq!(stack_contents(&uxn.wst), &[] as &[u8]);
+
+    assert_eq!(uxn.eval_from(0x0000), Ok(EvalOutcome::Break));
+    assert_eq!(stack_contents(&uxn.wst), &[0x2a]);
+
+    uxn.set_ram_slice(0x0100, &[0x80, 0x01, 0x80, 0x0f, 0x17])
+        .unwrap();
+    assert_eq!(uxn.eval_from(0x0100), Ok(EvalOutcome::Halted));
+    // once halted, nothing runs
+    assert_eq!(uxn.eval_from(0x0000), Ok(EvalOutcome::Halted));
+    assert_eq!(stack_contents(&uxn.wst), &[0x2a]);
+}
+
+#[test]
+fn console_input_kinds() {
+    use ConsoleInputKind::*;
+
+    // the console vector pushes each byte and its type
+    let mut uxn =
+        Uxn::try_from("|0100 ;on-console #10 DEO2 BRK @on-console #12 DEI #17 DEI BRK").unwrap();
+    uxn.set_device(devices::CONSOLE, Box::new(devices::ConsoleDevice::new()));
+    uxn.eval(0x100).unwrap();
+    for (byte, kind) in [
+        (b'a', Argument),
+        (b'\n', ArgumentSeparator),
+        (b'b', Argument),
+        (b'\n', Eof),
+        (b'x', Stdin),
+    ] {
+        uxn.feed_console(byte, kind).unwrap();
+    }
+    assert_eq!(
+        stack_contents(&uxn.wst),
+        &[b'a', 2, b'\n', 3, b'b', 2, b'\n', 4, b'x', 1]
+    );
+}
+
+#[test]
+fn state_hash_is_deterministic() {
+    let hash = |src: &str| {
+        let mut uxn = Uxn::try_from(src).unwrap();
+        uxn.eval(0x100).unwrap();
+        uxn.state_hash()
+    };
+    let src = "#1234 #10 STZ2 #05 #06 MUL STH BRK";
+    assert_eq!(hash(src), hash(src));
+    assert_ne!(hash(src), hash("#1234 #10 STZ2 #05 #07 MUL STH BRK"));
+    assert_ne!(hash(src), hash("#1234 #10 STZ2 #05 #06 MUL BRK"));
+}
+
+#[test]
+fn datetime_follows_the_clock() {
+    use crate::clock::ManualClock;
+    use crate::devices::{DateTimeDevice, DATETIME};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    // 2024-02-29 23:59:58
+    let clock = Arc::new(ManualClock::new(
+        UNIX_EPOCH + Duration::from_secs(1709251198),
+    ));
+    let mut uxn = Uxn::with_clock(clock.clone());
+    uxn.set_device(DATETIME, Box::new(DateTimeDevice::new(uxn.clock())));
+    uxn.set_device(devices::AUDIO[0], Box::new(AudioDevice::new()));
+    uxn.load_program(
+        &crate::assembler::assemble(
+            "|0100 #c0 DEI #c1 DEI #c2 DEI #c3 DEI #c4 DEI #c5 DEI #c6 DEI BRK
+            |0200 #c3 DEI #c2 DEI #c6 DEI #ff #38 DEO #01 #3f DEO BRK",
+        )
+        .unwrap()
+        .rom,
+        0x100,
+    );
+    uxn.eval(0x100).unwrap();
+    assert_eq!(
+        stack_contents(&uxn.wst),
+        &[0x07, 0xe8, 0x01, 29, 23, 59, 58]
+    );
+
+    clock.advance(Duration::from_secs(3));
+    assert_eq!(uxn.run_clocked_frame().unwrap().audio_samples, 0);
+    uxn.eval(0x200).unwrap();
+    assert_eq!(&stack_contents(&uxn.wst)[7..], &[1, 2, 1]);
+
+    // the note started by the second vector plays for as long as the clock advanced
+    clock.advance(Duration::from_millis(10));
+    assert_eq!(uxn.run_clocked_frame().unwrap().audio_samples, 441);
+}
+
+#[test]
+fn jump_widths_and_stacks() {
+    let mut uxn = Uxn::new();
+    uxn.boot();
+    let jump = |uxn: &mut Uxn, mode: InstructionMode| {
+        uxn.ram[0x200] = encode_byte(Opcode::JMP, mode);
+        uxn.set_pc(0x200);
+        assert_eq!(uxn.step(), EvalOutcome::Stepped);
+        uxn.pc()
+    };
+
+    // JMP2 pops an absolute address off the working stack
+    uxn.push16(0x1234, InstructionMode::None).unwrap();
+    assert_eq!(jump(&mut uxn, InstructionMode::Short), 0x1234);
+    assert_eq!(stack_contents(&uxn.wst), &[] as &[u8]);
+
+    // JMP2r pops it off the return stack, leaving the working stack alone
+    uxn.push8(0x99, InstructionMode::None).unwrap();
+    uxn.push16(0x0300, InstructionMode::Return).unwrap();
+    assert_eq!(
+        jump(&mut uxn, InstructionMode::Short | InstructionMode::Return),
+        0x0300
+    );
+    assert_eq!(stack_contents(&uxn.wst), &[0x99]);
+    assert_eq!(stack_contents(&uxn.rst), &[] as &[u8]);
+
+    // JMPr and JMP pop a byte, a signed offset from after the jump: 0x201 + 5, 0x201 - 0x67
+    uxn.push8(0x05, InstructionMode::Return).unwrap();
+    assert_eq!(jump(&mut uxn, InstructionMode::Return), 0x206);
+    assert_eq!(jump(&mut uxn, InstructionMode::None), 0x19a);
+    assert_eq!(stack_contents(&uxn.wst), &[] as &[u8]);
+    assert_eq!(stack_contents(&uxn.rst), &[] as &[u8]);
+}
+
+#[test]
+fn preset_device_memory() {
+    // a device whose ports are all plain device memory
+    struct Registers;
+    impl Device for Registers {
+        fn dei(&self, _port: PortAddress) -> ExecutionResult<u8> {
+            unreachable!()
+        }
+        fn deo(&mut self, _port: PortAddress, _value: u8) -> ExecutionResult<()> {
+            unreachable!()
+        }
+        fn dei_mask(&self) -> u16 {
+            0
+        }
+        fn deo_mask(&self) -> u16 {
+            0
+        }
+    }
+
+    let mut uxn = Uxn::try_from("#22 DEI2 #24 DEI2 #abcd #28 DEO2 BRK").unwrap();
+    uxn.set_device(devices::SCREEN, Box::new(Registers));
+    let mut registers = [0; 16];
+    registers[0x2..0x6].copy_from_slice(&[0x01, 0x40, 0x00, 0xf0]);
+    uxn.init_device_memory(devices::SCREEN, &registers);
+    uxn.eval(0x100).unwrap();
+    assert_eq!(stack_contents(&uxn.wst), &[0x01, 0x40, 0x00, 0xf0]);
+    assert_eq!(uxn.dump_devices()[0x28..0x2a], [0xab, 0xcd]);
+}
+
+#[test]
+fn eval_in_budgeted_chunks() {
+    // LIT, 256 times INC DUP LIT JCN, POP BRK
+    let mut uxn = Uxn::try_from("#00 @loop INC DUP ,loop JCN POP BRK").unwrap();
+    uxn.set_pc(0x100);
+    assert_eq!(uxn.eval_budget(1000), (EvalOutcome::Stepped, 1000));
+    assert_eq!(uxn.eval_budget(1000), (EvalOutcome::Break, 27));
+    assert_eq!(stack_contents(&uxn.wst), &[] as &[u8]);
+    assert_eq!(uxn.eval_budget(0), (EvalOutcome::Stepped, 0));
+}
+
+#[test]
+fn comparisons_push_one_byte() {
+    let uxn = run("|0100 #1234 #1234 EQU2 #1234 #1235 EQU2 #1234 #1235 NEQ2 #1234 DUP2 NEQ2 BRK");
+    assert_eq!(stack_contents(&uxn.wst), &[0x01, 0x00, 0x01, 0x00]);
+
+    let uxn = run("|0100 #0100 #00ff GTH2 #0100 #00ff LTH2 #1234 STH2 #1234 STH2 EQU2r BRK");
+    assert_eq!(stack_contents(&uxn.wst), &[0x01, 0x00]);
+    // EQU2r compares two shorts of the return stack and leaves a byte there
+    assert_eq!(stack_contents(&uxn.rst), &[0x01]);
+}
+
+#[test]
+fn trace_instructions() {
+    let mut uxn = Uxn::try_from("#01 #02 ADD BRK").unwrap();
+    let (outcome, trace) = uxn.trace_run(0x100, 10);
+    assert_eq!(outcome, EvalOutcome::Break);
+    assert_eq!(
+        trace,
+        "0100  #01          wst [01] rst []
+0102  #02          wst [01 02] rst []
+0104  ADD          wst [03] rst []
+0105  BRK          wst [03] rst []
+"
+    );
+
+    let (outcome, trace) = uxn.trace_run(0x100, 2);
+    assert_eq!(outcome, EvalOutcome::Stepped);
+    assert_eq!(
+        trace.lines().last(),
+        Some("0102  #02          wst [03 01 02] rst []")
+    );
+}
+
+#[test]
+fn reset_stacks_keeps_memory() {
+    let mut uxn = run("|0100 #1234 STH2 #56 #78 #9a #00 STZ BRK");
+    assert_eq!(stack_contents(&uxn.wst), &[0x56, 0x78]);
+    assert_eq!(stack_contents(&uxn.rst), &[0x12, 0x34]);
+    let ram = *uxn.memory();
+
+    uxn.reset_stacks();
+    assert_eq!(stack_contents(&uxn.wst), &[] as &[u8]);
+    assert_eq!(stack_contents(&uxn.rst), &[] as &[u8]);
+    assert_eq!(uxn.memory(), &ram);
+    assert_eq!(uxn.memory()[0x00], 0x9a);
+}
+
+#[test]
+fn read_metadata() {
+    let mut uxn = Uxn::try_from(";meta #06 DEO2 BRK @meta \"Demo 20 \"ROM 0a \"v1 00").unwrap();
+    assert_eq!(uxn.metadata(), None);
+    uxn.eval(0x100).unwrap();
+    assert_eq!(uxn.metadata(), Some(&b"Demo ROM\nv1"[..]));
+}
+
+#[test]
+fn halt_with_exit_code() {
+    let uxn = run("|0100 #80 #0f DEO BRK");
+    assert_eq!(uxn.exit_code(), Some(0));
+    let uxn = run("|0100 #01 #0f DEO BRK");
+    assert_eq!(uxn.exit_code(), Some(1));
+    let uxn = run("|0100 #ff #0f DEO BRK");
+    assert_eq!(uxn.exit_code(), Some(0x7f));
+    let uxn = run("|0100 #00 #0f DEO BRK");
+    assert_eq!(uxn.exit_code(), None);
+}
+
+#[test]
+fn immediate_jumps() {
+    use crate::devices::{ConsoleDevice, CONSOLE};
+
+    // JCI skips the block when the condition isn't zero, like uxn
+    let printed = |condition: &str| {
+        let src = format!("#{} ?{{ #21 #18 DEO }} #2e #18 DEO BRK", condition);
+        let mut uxn = Uxn::try_from(src.as_str()).unwrap();
+        uxn.set_device(CONSOLE, Box::new(ConsoleDevice::with_capture()));
+        uxn.eval(0x100).unwrap();
+        assert_eq!(uxn.wst.ptr, 0);
+        let console = uxn.device(CONSOLE).downcast_ref::<ConsoleDevice>().unwrap();
+        console.captured().to_vec()
+    };
+    assert_eq!(printed("01"), b".");
+    assert_eq!(printed("00"), b"!.");
+
+    let mut uxn = Uxn::try_from("!{ #01 } #02 BRK").unwrap();
+    uxn.eval(0x100).unwrap();
+    assert_eq!(stack_contents(&uxn.wst), &[0x02]);
+
+    // JSI to a routine at 0x0106 returning with JMP2r
+    let mut uxn = Uxn::new();
+    uxn.set_ram_slice(
+        0x0100,
+        &[JSI, 0x00, 0x03, 0x80, 0x02, 0x00, 0x80, 0x01, 0x6c],
+    )
+    .unwrap();
+    uxn.eval(0x100).unwrap();
+    assert_eq!(stack_contents(&uxn.wst), &[0x01, 0x02]);
+}
+
+#[test]
+fn device_names() {
+    use crate::clock::SystemClock;
+    use crate::devices::{
+        AudioDevice, ConsoleDevice, DateTimeDevice, ScreenDevice, AUDIO, CONSOLE, DATETIME, SCREEN,
+    };
+
+    let mut uxn = Uxn::new();
+    assert_eq!(uxn.device(CONSOLE).name(), "null");
+    assert_eq!(uxn.device_names(), vec![(0, "system")]);
+
+    uxn.set_device(CONSOLE, Box::new(ConsoleDevice::new()));
+    uxn.set_device(SCREEN, Box::new(ScreenDevice::new(64, 64)));
+    uxn.set_device(AUDIO[0], Box::new(AudioDevice::new()));
+    uxn.set_device(
+        DATETIME,
+        Box::new(DateTimeDevice::new(Arc::new(SystemClock::new()))),
+    );
+    assert_eq!(
+        uxn.device_names(),
+        vec![
+            (0x0, "system"),
+            (0x1, "console"),
+            (0x2, "screen"),
+            (0x3, "audio"),
+            (0xc, "datetime"),
+        ]
+    );
+}
+
+#[test]
+fn device_errors_name_the_device() {
+    struct Busy;
+
+    impl Device for Busy {
+        fn dei(&self, _port: PortAddress) -> ExecutionResult<u8> {
+            Err(UxnError::Device {
+                name: "",
+                reason: "busy",
+            })
+        }
+        fn deo(&mut self, _port: PortAddress, _value: u8) -> ExecutionResult<()> {
+            Ok(())
+        }
+        fn dei_mask(&self) -> u16 {
+            0xffff
+        }
+        fn name(&self) -> &'static str {
+            "busy"
+        }
+    }
+
+    let mut uxn = Uxn::try_from("#a0 DEI").unwrap();
+    uxn.set_device(0xa, Box::new(Busy));
+    let fault = uxn.eval(0x100).unwrap_err();
+    let busy = UxnError::Device {
+        name: "busy",
+        reason: "busy",
+    };
+    assert_eq!(fault.error, busy);
+    assert!(fault.to_string().starts_with("busy device: busy at 0102"));
 }
@@ -0,0 +1,158 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("uxn-cli-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn assemble_writes_rom_and_symbols() {
+    let rom = scratch_dir("assemble").join("hello.rom");
+    let output = Command::new(env!("CARGO_BIN_EXE_uxn"))
+        .arg("assemble")
+        .arg(fixture("hello.tal"))
+        .arg("-o")
+        .arg(&rom)
+        .arg("--sym")
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    assert_eq!(
+        fs::read(&rom).unwrap(),
+        fs::read(fixture("hello.rom")).unwrap()
+    );
+    let sym = fs::read(rom.with_extension("rom.sym")).unwrap();
+    assert!(sym.starts_with(b"\x01\x00on-reset\x00\x01\x03on-reset/loop\x00"));
+}
+
+#[test]
+fn assemble_reports_errors_with_caret() {
+    let rom = scratch_dir("error").join("unknown_label.rom");
+    let output = Command::new(env!("CARGO_BIN_EXE_uxn"))
+        .arg("assemble")
+        .arg(fixture("unknown_label.tal"))
+        .arg("-o")
+        .arg(&rom)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(!rom.exists());
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("error: unknown label `hello-wrold`"),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("unknown_label.tal:2:2"), "{}", stderr);
+    assert!(
+        stderr.contains("2 | \t;hello-wrold JSR2\n  | \t^^^^^^^^^^^^\n"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn disasm_prints_labels_and_instructions() {
+    let output = Command::new(env!("CARGO_BIN_EXE_uxn"))
+        .arg("disasm")
+        .arg(fixture("hello.rom"))
+        .arg("--sym")
+        .arg(fixture("hello.rom.sym"))
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        fs::read_to_string(fixture("hello.disasm")).unwrap()
+    );
+
+    // without symbols, addresses stay numeric
+    let output = Command::new(env!("CARGO_BIN_EXE_uxn"))
+        .arg("disasm")
+        .arg(fixture("hello.rom"))
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("0100  #010f\n0103  LDAk\n"));
+}
+
+#[test]
+fn assemble_to_intel_hex() {
+    let hex = scratch_dir("hex").join("hello.hex");
+    let output = Command::new(env!("CARGO_BIN_EXE_uxn"))
+        .arg("assemble")
+        .arg(fixture("hello.tal"))
+        .arg("-o")
+        .arg(&hex)
+        .arg("--format")
+        .arg("hex")
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let hex = fs::read_to_string(&hex).unwrap();
+    let records: Vec<&str> = hex.lines().collect();
+    assert_eq!(
+        records,
+        vec![
+            ":10010000A0010F948018172194A001032D2200480C",
+            ":0D011000656C6C6F20576F726C64210A00E3",
+            ":00000001FF",
+        ]
+    );
+    // the bytes of every record, checksum included, add up to zero
+    for record in records {
+        let sum = (1..record.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&record[i..i + 2], 16).unwrap())
+            .fold(0u8, |sum, b| sum.wrapping_add(b));
+        assert_eq!(sum, 0, "{}", record);
+    }
+}
+
+#[test]
+fn run_exits_with_the_rom_exit_code() {
+    let dir = scratch_dir("run");
+    let run = |name: &str, src: &str| {
+        let rom = dir.join(name);
+        fs::write(&rom, uxn_rs::assembler::assemble(src).unwrap().rom).unwrap();
+        Command::new(env!("CARGO_BIN_EXE_uxn"))
+            .arg("run")
+            .arg(&rom)
+            .output()
+            .unwrap()
+    };
+
+    let output = run("hi.rom", "|0100 #48 #18 DEO #69 #18 DEO BRK");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"Hi");
+
+    let output = run("exit.rom", "|0100 #83 #0f DEO BRK");
+    assert_eq!(output.status.code(), Some(3));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_uxn"))
+        .arg("run")
+        .arg(fixture("hello.rom"))
+        .output()
+        .unwrap();
+    assert_eq!(output.stdout, b"Hello World!\n");
+}
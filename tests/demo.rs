@@ -0,0 +1,20 @@
+#[path = "../examples/demo.rs"]
+mod demo;
+
+use uxn_rs::devices::{ScreenDevice, SCREEN};
+
+#[test]
+fn demo_draws_the_screen() {
+    let uxn = demo::run();
+    let screen = uxn.device(SCREEN).downcast_ref::<ScreenDevice>().unwrap();
+    assert_eq!(screen.pixel(0, 0), 0);
+    assert_eq!(screen.pixel(8, 0), 1);
+    assert_eq!(screen.pixel(0, 16), 2);
+    assert_eq!(screen.pixel(24, 0), 3);
+
+    let ppm = demo::ppm(&uxn);
+    assert!(ppm.starts_with(b"P6\n64 64\n255\n"));
+    assert_eq!(ppm.len(), 13 + 64 * 64 * 3);
+    // pixel (8, 0) is white
+    assert_eq!(&ppm[13 + 8 * 3..13 + 9 * 3], &[0xff, 0xff, 0xff]);
+}
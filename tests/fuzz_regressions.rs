@@ -0,0 +1,38 @@
+//! Runs every ROM in `tests/corpus` for a bounded number of instructions: inputs that once
+//! panicked the VM must now only stop on a `BRK`, a halt, a fault or the budget.
+
+use std::fs;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::PathBuf;
+use uxn_rs::uxn::Uxn;
+
+const INSTRUCTION_LIMIT: u64 = 10_000;
+
+#[test]
+fn corpus_does_not_panic() {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/corpus");
+    let mut roms: Vec<PathBuf> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "rom"))
+        .collect();
+    roms.sort();
+    assert!(!roms.is_empty(), "no ROMs in {}", dir.display());
+
+    let panicked: Vec<String> = roms
+        .iter()
+        .filter(|path| {
+            let rom = fs::read(path).unwrap();
+            catch_unwind(AssertUnwindSafe(|| {
+                // ROMs too large to load are rejected, not run
+                if let Ok(mut uxn) = Uxn::try_from(rom.as_slice()) {
+                    uxn.set_pc(0x100);
+                    uxn.eval_budget(INSTRUCTION_LIMIT);
+                }
+            }))
+            .is_err()
+        })
+        .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+        .collect();
+    assert!(panicked.is_empty(), "corpus ROMs panicked: {:?}", panicked);
+}
@@ -0,0 +1,31 @@
+use std::path::PathBuf;
+use uxn_rs::assembler::assemble;
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+#[test]
+fn varvara_device_header() {
+    let src = std::fs::read_to_string(fixture("varvara.tal")).unwrap();
+    let assembled = assemble(&src).unwrap();
+    let label = |name: &str| {
+        assembled
+            .symbols
+            .iter()
+            .find(|(_, label)| label == name)
+            .map(|(address, _)| *address)
+    };
+
+    assert_eq!(label("System/vector"), Some(0x00));
+    assert_eq!(label("System/wst"), Some(0x02));
+    assert_eq!(label("System/r"), Some(0x08));
+    assert_eq!(label("System/state"), Some(0x0f));
+    assert_eq!(label("Console/write"), Some(0x18));
+    assert_eq!(label("Screen/pixel"), Some(0x2e));
+    // the header emits nothing, the ROM starts with the program at 0x0100
+    assert_eq!(&assembled.rom[..4], &[0xa0, 0xf0, 0x7f, 0x80]);
+    assert_eq!(assembled.rom[4], 0x08);
+}
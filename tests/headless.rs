@@ -0,0 +1,36 @@
+use std::path::PathBuf;
+use uxn_rs::uxn::{AppError, EvalOutcome, Uxn};
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+#[test]
+fn echo_rom_echoes_its_input() {
+    let input = b"hello, uxn\n";
+    let (outcome, output) = Uxn::run_headless(fixture("echo.rom"), input, 10_000).unwrap();
+    assert_eq!(outcome, EvalOutcome::Break);
+    assert_eq!(output, input);
+}
+
+#[test]
+fn headless_runs_stop_at_the_limit() {
+    // the reset vector takes 4 instructions and each byte 10, the limit runs out on the second
+    // byte
+    let (outcome, output) = Uxn::run_headless(fixture("echo.rom"), b"abc", 19).unwrap();
+    assert_eq!(outcome, EvalOutcome::Stepped);
+    assert_eq!(output, b"a");
+}
+
+#[test]
+fn headless_reports_missing_roms() {
+    assert!(matches!(
+        Uxn::run_headless(fixture("missing.rom"), b"", 100),
+        Err(AppError::Io {
+            kind: std::io::ErrorKind::NotFound,
+            ..
+        })
+    ));
+}
@@ -0,0 +1,65 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use uxn_rs::assembler::assemble;
+use uxn_rs::encoder::{encode_into, EncodeError};
+
+// counts the allocations of the current thread, the test harness allocates on its own threads
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+#[test]
+fn encode_without_allocating() {
+    let src = "( greet ) #68 #18 DEO [ #1269 ] SWP2k ADDr LIT 2a \"hi 1234 BRK";
+    let mut buffer = [0u8; 32];
+
+    let before = ALLOCATIONS.with(|count| count.get());
+    let len = encode_into(src, &mut buffer).unwrap();
+    let after = ALLOCATIONS.with(|count| count.get());
+    assert_eq!(before, after);
+
+    assert_eq!(
+        &buffer[..len],
+        &[
+            0x80, 0x68, 0x80, 0x18, 0x17, 0xa0, 0x12, 0x69, 0xa4, 0x58, 0x80, 0x2a, b'h', b'i',
+            0x12, 0x34, 0x00
+        ]
+    );
+    assert_eq!(
+        &buffer[..len],
+        &assemble(&format!("|0100 {}", src)).unwrap().rom[..]
+    );
+}
+
+#[test]
+fn encode_errors() {
+    let mut buffer = [0u8; 4];
+    assert_eq!(
+        encode_into("#01 ;main JMP2", &mut buffer),
+        Err(EncodeError::NeedsAssembler { at: 4 })
+    );
+    assert_eq!(
+        encode_into("#01 FOO", &mut buffer),
+        Err(EncodeError::Unrecognized { at: 4 })
+    );
+    assert_eq!(
+        encode_into("#01 #0203", &mut buffer),
+        Err(EncodeError::BufferFull { at: 4 })
+    );
+}
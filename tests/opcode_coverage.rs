@@ -0,0 +1,98 @@
+//! Runs a small program for each of the 32 opcodes, in byte mode, and checks its result.
+
+use uxn_rs::uxn::{Opcode, RunOutcome, Uxn};
+
+struct Case {
+    /// The opcode under test, as its mnemonic.
+    opcode: &'static str,
+    src: &'static str,
+    wst: &'static [u8],
+    rst: &'static [u8],
+    console: &'static [u8],
+}
+
+const fn case(opcode: &'static str, src: &'static str, wst: &'static [u8]) -> Case {
+    Case {
+        opcode,
+        src,
+        wst,
+        rst: &[],
+        console: &[],
+    }
+}
+
+const CASES: &[Case] = &[
+    case("LIT", "#12 BRK #34", &[0x12]),
+    case("INC", "#ff INC #01 INC", &[0x00, 0x02]),
+    case("POP", "#01 #02 POP", &[0x01]),
+    case("NIP", "#01 #02 NIP", &[0x02]),
+    case("SWP", "#01 #02 SWP", &[0x02, 0x01]),
+    case("ROT", "#01 #02 #03 ROT", &[0x02, 0x03, 0x01]),
+    case("DUP", "#01 DUP", &[0x01, 0x01]),
+    case("OVR", "#01 #02 OVR", &[0x01, 0x02, 0x01]),
+    case("EQU", "#01 #01 EQU #01 #02 EQU", &[0x01, 0x00]),
+    case("NEQ", "#01 #01 NEQ #01 #02 NEQ", &[0x00, 0x01]),
+    case("GTH", "#02 #01 GTH #01 #02 GTH", &[0x01, 0x00]),
+    case("LTH", "#02 #01 LTH #01 #02 LTH", &[0x00, 0x01]),
+    case("JMP", "#02 JMP #ff #01", &[0x01]),
+    // the first jump is not taken, the second skips #bb
+    case("JCN", "#00 #02 JCN #aa #01 #02 JCN #bb", &[0xaa]),
+    Case {
+        rst: &[0x01, 0x03],
+        ..case("JSR", "#02 JSR #ff #01", &[0x01])
+    },
+    Case {
+        rst: &[0x02],
+        ..case("STH", "#01 #02 STH", &[0x01])
+    },
+    case("LDZ", "#2a #10 STZ #10 LDZ", &[0x2a]),
+    case("STZ", "#2a #10 STZ #10 LDZ", &[0x2a]),
+    case("LDR", ",data LDR BRK @data 2a", &[0x2a]),
+    case("STR", "#2a ,data STR ,data LDR BRK @data 00", &[0x2a]),
+    case("LDA", ";data LDA BRK @data 2a", &[0x2a]),
+    case("STA", "#2a ;data STA ;data LDA BRK @data 00", &[0x2a]),
+    // the system device's working stack pointer, read after the port is popped
+    case("DEI", "#aa #02 DEI", &[0xaa, 0x01]),
+    Case {
+        console: b"hi",
+        ..case("DEO", "#68 #18 DEO #69 #18 DEO", &[])
+    },
+    case("ADD", "#01 #02 ADD #ff #02 ADD", &[0x03, 0x01]),
+    case("SUB", "#03 #01 SUB #01 #02 SUB", &[0x02, 0xff]),
+    case("MUL", "#03 #04 MUL #10 #10 MUL", &[0x0c, 0x00]),
+    case("DIV", "#0c #04 DIV #0d #04 DIV", &[0x03, 0x03]),
+    case("AND", "#0c #0a AND", &[0x08]),
+    case("ORA", "#0c #0a ORA", &[0x0e]),
+    case("EOR", "#0c #0a EOR", &[0x06]),
+    // right by one, then left by two
+    case("SFT", "#34 #21 SFT", &[0x68]),
+];
+
+#[test]
+fn every_opcode() {
+    let mut failures = vec![];
+    for case in CASES {
+        let expected = RunOutcome {
+            outcome: uxn_rs::uxn::EvalOutcome::Break,
+            wst: case.wst.to_vec(),
+            rst: case.rst.to_vec(),
+            console: case.console.to_vec(),
+        };
+        match Uxn::assemble_and_run(case.src) {
+            Ok(run) if run == expected => {}
+            result => failures.push(format!(
+                "{}: `{}` gave {:?}, expected {:?}",
+                case.opcode, case.src, result, expected
+            )),
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+#[test]
+fn cases_cover_every_opcode() {
+    let covered: Vec<Opcode> = CASES.iter().map(|c| c.opcode.parse().unwrap()).collect();
+    for opcode in Opcode::all() {
+        assert!(covered.contains(opcode), "no case for {:?}", opcode);
+    }
+}
@@ -0,0 +1,66 @@
+use uxn_rs::assembler::assemble;
+use uxn_rs::uxn::{InstructionMode, Uxn};
+
+fn run(src: &str) -> Uxn {
+    let mut uxn = Uxn::new();
+    uxn.boot();
+    uxn.load_program(&assemble(src).unwrap().rom, 0x100);
+    uxn.eval(0x100).unwrap();
+    uxn
+}
+
+fn zero_page(uxn: &mut Uxn, addr: usize) -> u16 {
+    uxn.peek(addr, InstructionMode::None).unwrap()
+}
+
+#[test]
+fn jsr2_returns_with_jmp2r() {
+    let mut uxn = run("
+        |0100
+            #01 ;add-two JSR2 #00 STZ
+            #ff #01 STZ
+        BRK
+
+        @add-two ( a -- a+2 )
+            #02 ADD
+        JMP2r
+    ");
+    assert_eq!(zero_page(&mut uxn, 0x00), 0x03);
+    // execution resumed after the call
+    assert_eq!(zero_page(&mut uxn, 0x01), 0xff);
+}
+
+#[test]
+fn nested_subroutines_return_in_order() {
+    let mut uxn = run("
+        |0100
+            #01 ;outer JSR2 #00 STZ
+        BRK
+
+        @outer ( a -- a*2+1 )
+            ;double JSR2 INC
+        JMP2r
+
+        @double ( a -- a*2 )
+            DUP ADD
+        JMP2r
+    ");
+    assert_eq!(zero_page(&mut uxn, 0x00), 0x03);
+}
+
+#[test]
+fn jsr2r_returns_with_jmp2() {
+    // in return mode the call address comes from, and the return address goes to, the other stacks
+    let mut uxn = run("
+        |0100
+            ;sub STH2 JSR2r
+            #ff #00 STZ
+        BRK
+
+        @sub
+            #aa #01 STZ
+        JMP2
+    ");
+    assert_eq!(zero_page(&mut uxn, 0x00), 0xff);
+    assert_eq!(zero_page(&mut uxn, 0x01), 0xaa);
+}